@@ -6,6 +6,8 @@ fn main() {
         .input("src/protos/query.proto")
         .input("src/protos/activate.proto")
         .input("src/protos/subscribe.proto")
+        .input("src/protos/hello.proto")
+        .input("src/protos/error.proto")
         .include("src/protos")
         .run_from_script();
 }
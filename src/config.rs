@@ -2,7 +2,7 @@ use config::{Config, ConfigError, File, FileFormat};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::OnceLock};
 
-use crate::{keybinds::Action, state::set_error};
+use crate::{keybinds::Action, providers::dmenu::Match, state::set_error};
 
 static LOADED_CONFIG: OnceLock<Walker> = OnceLock::new();
 const DEFAULT_CONFIG: &str = include_str!("../resources/config.toml");
@@ -15,6 +15,7 @@ pub struct Walker {
     pub click_to_close: bool,
     pub close_when_open: bool,
     pub selection_wrap: bool,
+    pub query_debounce: u64,
     pub global_argument_delimiter: String,
     pub theme: String,
     pub exact_search_prefix: String,
@@ -22,8 +23,12 @@ pub struct Walker {
     pub installed_providers: Option<Vec<String>>,
     pub keybinds: Keybinds,
     pub shell: Shell,
+    pub elephant: Elephant,
+    pub sections: Sections,
+    pub dmenu: Dmenu,
     pub additional_theme_location: Option<String>,
     pub placeholders: Option<HashMap<String, Placeholder>>,
+    pub subscriptions: Vec<Subscription>,
 }
 
 // Partial config for user overrides
@@ -43,6 +48,8 @@ struct PartialWalker {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub selection_wrap: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_debounce: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub global_argument_delimiter: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub theme: Option<String>,
@@ -57,9 +64,17 @@ struct PartialWalker {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shell: Option<PartialShell>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub elephant: Option<PartialElephant>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sections: Option<PartialSections>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dmenu: Option<PartialDmenu>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub additional_theme_location: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub placeholders: Option<HashMap<String, Placeholder>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscriptions: Option<Vec<Subscription>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -81,6 +96,8 @@ struct PartialProviders {
     pub actions: Option<HashMap<String, Vec<Action>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_results_provider: Option<HashMap<String, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weights: Option<HashMap<String, f64>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -98,6 +115,10 @@ struct PartialKeybinds {
     pub resume_last_query: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub quick_activate: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toggle_mark: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mark_all: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -113,6 +134,43 @@ struct PartialShell {
     pub anchor_right: Option<bool>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+struct PartialElephant {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autostart: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backends: Option<Vec<Backend>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+struct PartialSections {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub providers: Option<HashMap<String, SectionHeader>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+struct PartialDmenu {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_binds: Option<HashMap<String, i32>>,
+    #[serde(rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matching: Option<Match>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case_sensitive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuzzy_threshold: Option<u32>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
 struct PartialClipboard {
@@ -122,11 +180,7 @@ struct PartialClipboard {
 
 impl Walker {
     pub fn new() -> Result<Self, ConfigError> {
-        let default_config = Config::builder()
-            .add_source(File::from_str(DEFAULT_CONFIG, FileFormat::Toml))
-            .build()?;
-
-        let mut config: Walker = default_config.try_deserialize()?;
+        let mut config = Walker::from_defaults()?;
 
         if let Some(user_config_path) =
             xdg::BaseDirectories::with_prefix("walker").find_config_file("config.toml")
@@ -155,6 +209,13 @@ impl Walker {
         Ok(config)
     }
 
+    fn from_defaults() -> Result<Self, ConfigError> {
+        Config::builder()
+            .add_source(File::from_str(DEFAULT_CONFIG, FileFormat::Toml))
+            .build()?
+            .try_deserialize()
+    }
+
     fn merge(&mut self, partial: PartialWalker) {
         if let Some(v) = partial.debug {
             self.debug = v;
@@ -174,6 +235,9 @@ impl Walker {
         if let Some(v) = partial.selection_wrap {
             self.selection_wrap = v;
         }
+        if let Some(v) = partial.query_debounce {
+            self.query_debounce = v;
+        }
         if let Some(v) = partial.global_argument_delimiter {
             self.global_argument_delimiter = v;
         }
@@ -192,6 +256,19 @@ impl Walker {
         if let Some(v) = partial.placeholders {
             self.placeholders = Some(v);
         }
+        // by provider, so the defaults stay unless overridden
+        if let Some(v) = partial.subscriptions {
+            for s in v {
+                match self
+                    .subscriptions
+                    .iter_mut()
+                    .find(|d| d.provider == s.provider)
+                {
+                    Some(d) => *d = s,
+                    None => self.subscriptions.push(s),
+                }
+            }
+        }
 
         if let Some(p) = partial.providers {
             self.providers.merge(p);
@@ -202,10 +279,32 @@ impl Walker {
         if let Some(s) = partial.shell {
             self.shell.merge(s);
         }
+        if let Some(e) = partial.elephant {
+            self.elephant.merge(e);
+        }
+        if let Some(s) = partial.sections {
+            self.sections.merge(s);
+        }
+        if let Some(d) = partial.dmenu {
+            self.dmenu.merge(d);
+        }
     }
 }
 
 impl Providers {
+    // "menus:other" falls back to the weight of "menus"
+    pub fn weight(&self, provider: &str) -> f64 {
+        self.weights
+            .get(provider)
+            .or_else(|| {
+                provider
+                    .split_once(':')
+                    .and_then(|(base, _)| self.weights.get(base))
+            })
+            .copied()
+            .unwrap_or(1.0)
+    }
+
     fn merge(&mut self, partial: PartialProviders) {
         if let Some(v) = partial.default {
             self.default = v;
@@ -229,6 +328,10 @@ impl Providers {
             });
         }
 
+        if let Some(v) = partial.weights {
+            self.weights.extend(v);
+        }
+
         if let Some(v) = partial.actions {
             v.iter().for_each(|(key, value)| {
                 if !self.actions.contains_key(key) {
@@ -280,6 +383,12 @@ impl Keybinds {
         if let Some(v) = partial.quick_activate {
             self.quick_activate = Some(v);
         }
+        if let Some(v) = partial.toggle_mark {
+            self.toggle_mark = v;
+        }
+        if let Some(v) = partial.mark_all {
+            self.mark_all = v;
+        }
     }
 }
 
@@ -300,6 +409,69 @@ impl Shell {
     }
 }
 
+impl Elephant {
+    fn merge(&mut self, partial: PartialElephant) {
+        if let Some(v) = partial.socket {
+            self.socket = v;
+        }
+        if let Some(v) = partial.autostart {
+            self.autostart = v;
+        }
+        if let Some(v) = partial.error_timeout {
+            self.error_timeout = v;
+        }
+        if let Some(v) = partial.backends {
+            self.backends = v;
+        }
+    }
+}
+
+impl Sections {
+    // "menus:other" falls back to "menus", without a name the provider's is shown
+    pub fn header(&self, provider: &str) -> (String, Option<String>) {
+        let header = self.providers.get(provider).or_else(|| {
+            provider
+                .split_once(':')
+                .and_then(|(base, _)| self.providers.get(base))
+        });
+
+        let name = header
+            .and_then(|h| h.name.clone())
+            .unwrap_or_else(|| provider.to_string());
+
+        (name, header.and_then(|h| h.icon.clone()))
+    }
+
+    fn merge(&mut self, partial: PartialSections) {
+        if let Some(v) = partial.enabled {
+            self.enabled = v;
+        }
+        if let Some(v) = partial.providers {
+            self.providers.extend(v);
+        }
+    }
+}
+
+impl Dmenu {
+    fn merge(&mut self, partial: PartialDmenu) {
+        if let Some(v) = partial.custom_binds {
+            self.custom_binds.extend(v);
+        }
+        if let Some(v) = partial.matching {
+            self.matching = v;
+        }
+        if let Some(v) = partial.case_sensitive {
+            self.case_sensitive = v;
+        }
+        if let Some(v) = partial.sort {
+            self.sort = v;
+        }
+        if let Some(v) = partial.fuzzy_threshold {
+            self.fuzzy_threshold = v;
+        }
+    }
+}
+
 impl Clipboard {
     fn merge(&mut self, partial: PartialClipboard) {
         if let Some(v) = partial.time_format {
@@ -319,12 +491,76 @@ pub struct Shell {
     pub anchor_right: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Elephant {
+    pub socket: String,
+    pub autostart: bool,
+    pub error_timeout: u64,
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backend {
+    pub socket: String,
+    pub providers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sections {
+    pub enabled: bool,
+    #[serde(default)]
+    pub providers: HashMap<String, SectionHeader>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SectionHeader {
+    pub name: Option<String>,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dmenu {
+    #[serde(default)]
+    pub custom_binds: HashMap<String, i32>,
+    #[serde(rename = "match")]
+    pub matching: Match,
+    pub case_sensitive: bool,
+    pub sort: bool,
+    pub fuzzy_threshold: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Placeholder {
     pub input: String,
     pub list: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub provider: String,
+    #[serde(default)]
+    pub interval: i32,
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub effects: HashMap<String, SubscriptionEffect>,
+    // skipped if the provider isn't installed
+    #[serde(default)]
+    pub if_installed: bool,
+}
+
+// what to do with a value pushed by a subscription. `%VALUE%` is replaced
+// with the value itself.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SubscriptionEffect {
+    pub placeholder: Option<String>,
+    pub css_class: Option<String>,
+    pub open: Option<String>,
+    #[serde(default)]
+    pub refresh: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Keybinds {
     pub close: Vec<String>,
@@ -333,6 +569,8 @@ pub struct Keybinds {
     pub toggle_exact: Vec<String>,
     pub resume_last_query: Vec<String>,
     pub quick_activate: Option<Vec<String>>,
+    pub toggle_mark: Vec<String>,
+    pub mark_all: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -341,6 +579,7 @@ pub struct Providers {
     pub empty: Vec<String>,
     pub max_results: i32,
     pub max_results_provider: HashMap<String, i32>,
+    pub weights: HashMap<String, f64>,
     pub prefixes: Vec<Prefix>,
     pub clipboard: Clipboard,
     pub actions: HashMap<String, Vec<Action>>,
@@ -370,6 +609,12 @@ pub fn load() -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|_| "Failed to set loaded config".into())
 }
 
+// tests shouldn't depend on whatever config the machine running them has
+#[cfg(test)]
+pub fn load_defaults() {
+    LOADED_CONFIG.get_or_init(|| Walker::from_defaults().expect("invalid default config"));
+}
+
 pub fn get_config() -> &'static Walker {
     LOADED_CONFIG.get().expect("config not initialized")
 }
@@ -1,409 +1,591 @@
-use crate::config::get_config;
+use crate::config::{Subscription, get_config};
+use crate::elephant::backend::{Backend, route};
+use crate::elephant::frame::FRAME_SUBSCRIBE;
+use crate::elephant::reconnect::{Backoff, wait_for_restart, wait_for_socket};
+use crate::elephant::{ConnectError, ElephantClient, Request, Response};
 use crate::keybinds::{Action, AfterAction};
 use crate::protos::generated_proto::activate::ActivateRequest;
+use crate::protos::generated_proto::error::ErrorResponse;
+use crate::protos::generated_proto::query::query_response::Item;
 use crate::protos::generated_proto::query::{QueryRequest, QueryResponse};
 use crate::protos::generated_proto::subscribe::SubscribeRequest;
-use crate::protos::generated_proto::subscribe::SubscribeResponse;
 use crate::providers::PROVIDERS;
+use crate::providers::dmenu::{self, Format};
 use crate::state::{
-    get_async_after, get_current_prefix, get_current_set, get_provider, is_connected,
-    is_connecting, is_dmenu, is_service, set_async_after, set_current_prefix, set_is_connected,
-    set_is_connecting, set_is_visible, set_prefix_provider, set_provider, set_query,
+    ConnectionState, can_query, get_async_after, get_connection_state, get_current_prefix,
+    get_current_set, get_dmenu_accept_nth, get_dmenu_delimiter, get_dmenu_format, get_error,
+    get_provider, get_socket, has_elephant, is_dmenu, is_dmenu_multi, is_service, set_async_after,
+    set_connection_state, set_current_prefix, set_error, set_is_visible, set_prefix_provider,
+    set_provider, set_query,
 };
-use crate::ui::window::{set_input_text, set_keybind_hint, with_window};
+use crate::ui::dmenu_list;
+use crate::ui::window::{WindowData, set_input_text, set_keybind_hint, with_window};
 use crate::{QueryResponseObject, handle_preview, send_message};
 use gtk4::glib::Object;
 use gtk4::{glib, prelude::*};
-use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
-use nucleo_matcher::{Config, Matcher};
-use protobuf::{Message, MessageField};
-use std::cmp::Ordering;
+use protobuf::MessageField;
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::io::{BufReader, Read, Write};
-use std::os::unix::net::UnixStream;
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use std::{env, thread};
+use std::{env, iter, mem, thread};
 
-static CONN: Mutex<Option<UnixStream>> = Mutex::new(None);
-static MENUCONN: Mutex<Option<UnixStream>> = Mutex::new(None);
-static BLUETOOTHCONN: Mutex<Option<UnixStream>> = Mutex::new(None);
+static BACKENDS: OnceLock<Vec<Arc<Backend>>> = OnceLock::new();
+
+const RECONNECT_BASE: Duration = Duration::from_millis(100);
+const RECONNECT_MAX: Duration = Duration::from_secs(10);
+
+// bumped with every query. The list shows results of the previous query until
+// the first result of the current one arrives from any backend.
+static QUERY_GENERATION: AtomicU64 = AtomicU64::new(0);
+static SHOWN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+static PENDING: Mutex<Vec<(Arc<Backend>, Response)>> = Mutex::new(Vec::new());
+static FLUSH_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+// the last error a provider reported, hidden again with the next query
+static PROVIDER_ERROR: Mutex<String> = Mutex::new(String::new());
+
+thread_local! {
+    static QUERY_DEBOUNCE: RefCell<Option<glib::SourceId>> = const { RefCell::new(None) };
+}
 
 pub fn input_changed(text: &str) {
     set_current_prefix(String::new());
 
     with_window(|w| {
-        let is_empty = if text.is_empty() {
+        if text.is_empty() {
             w.window.remove_css_class("has-input");
-            true
         } else {
             w.window.add_css_class("has-input");
-            false
-        };
+        }
 
         if is_dmenu() {
-            if is_empty {
-                set_query("");
-
-                let list_store = &w.items;
-
-                list_store
-                    .iter()
-                    .flatten()
-                    .map(Object::downcast::<QueryResponseObject>)
-                    .filter_map(Result::ok)
-                    .for_each(|i| i.set_dmenu_score(0));
-            } else {
-                set_query(text);
-            }
-
-            sort_items_fuzzy(text);
-        } else if is_connected() {
             set_query(text);
-            query(text);
+            dmenu_list::set_query(text);
+        } else if can_query() {
+            set_query(text);
+            debounce_query(text);
         }
     });
 }
 
-fn sort_items_fuzzy(query: &str) {
-    with_window(|w| {
-        let list_store = &w.items;
+pub fn init_socket() {
+    let backends = backends();
 
-        let mut items: Vec<QueryResponseObject> = list_store
-            .iter()
-            .flatten()
-            .map(Object::downcast::<QueryResponseObject>)
-            .filter_map(Result::ok)
-            .collect();
+    // additional instances come and go on their own, only the primary one
+    // decides whether walker is usable
+    backends.iter().skip(1).cloned().for_each(|backend| {
+        thread::spawn(move || connect_backend(&backend));
+    });
 
-        list_store.remove_all();
+    connect_backend(&backends[0]);
+}
 
-        if query.is_empty() {
-            items.sort_by(|a, b| {
-                let score_a = a.response().item.as_ref().map(|i| i.score);
-                let score_b = b.response().item.as_ref().map(|i| i.score);
-                score_b.cmp(&score_a)
-            });
-        } else {
-            let texts = items
-                .iter()
-                .map(QueryResponseObject::response)
-                .map(|response| response.item)
-                .filter_map(MessageField::into_option)
-                .map(|item| item.text);
-
-            let mut matcher = Matcher::new(Config::DEFAULT.match_paths());
-            let pattern = Pattern::parse(query, CaseMatching::Ignore, Normalization::Smart);
-            let matches: Vec<(String, u32)> = pattern.match_list(texts, &mut matcher);
-            let score_map: HashMap<String, u32> = HashMap::from_iter(matches);
-
-            items.sort_by(|a, b| {
-                let ra = a.response();
-                let text_a = ra
-                    .item
-                    .as_ref()
-                    .map(|i| i.text.as_str())
-                    .unwrap_or_default();
-                let rb = b.response();
-                let text_b = rb
-                    .item
-                    .as_ref()
-                    .map(|i| i.text.as_str())
-                    .unwrap_or_default();
-
-                match (score_map.get(text_a), score_map.get(text_b)) {
-                    (Some(aa), Some(bb)) => {
-                        a.set_dmenu_score(*aa);
-                        b.set_dmenu_score(*bb);
-                        bb.cmp(aa)
-                    }
-                    (Some(aa), None) => {
-                        a.set_dmenu_score(*aa);
-                        Ordering::Less
-                    }
-                    (None, Some(bb)) => {
-                        b.set_dmenu_score(*bb);
-                        Ordering::Greater
-                    }
-                    (None, None) => text_a.cmp(text_b),
-                }
-            });
-        }
+fn backends() -> &'static [Arc<Backend>] {
+    BACKENDS.get_or_init(|| {
+        let cfg = &get_config().elephant;
 
-        list_store.extend_from_slice(&items);
-    });
+        let primary = [get_socket(), cfg.socket.clone()]
+            .into_iter()
+            .find(|s| !s.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(default_socket_path);
+
+        iter::once(Backend::new(primary, Vec::new()))
+            .chain(
+                cfg.backends
+                    .iter()
+                    .map(|b| Backend::new(PathBuf::from(&b.socket), b.providers.clone())),
+            )
+            .map(Arc::new)
+            .collect()
+    })
 }
 
-pub fn init_socket() -> Result<(), Box<dyn std::error::Error>> {
-    if is_connecting() {
-        return Ok(());
-    }
+pub fn primary_socket() -> PathBuf {
+    backends()[0].socket.clone()
+}
 
-    set_is_connecting(true);
-    println!("connecting to elephant...");
+fn is_primary(backend: &Arc<Backend>) -> bool {
+    Arc::ptr_eq(backend, &backends()[0])
+}
 
+fn default_socket_path() -> PathBuf {
     let mut socket_path = env::var("XDG_RUNTIME_DIR")
         .map(PathBuf::from)
         .unwrap_or_else(|_| env::temp_dir());
 
     socket_path.push("elephant");
     socket_path.push("elephant.sock");
+    socket_path
+}
 
-    println!("waiting for elephant to start...");
-    wait_for_file(&socket_path.to_string_lossy().to_string());
+fn connect_backend(backend: &Arc<Backend>) {
+    if !backend.try_start_connecting() {
+        return;
+    }
 
-    let conn = loop {
-        match UnixStream::connect(&socket_path) {
-            Ok(conn) => break conn,
-            Err(e) => {
-                println!("Failed to connect: {e}. Retrying in 1 second...");
-                thread::sleep(Duration::from_secs(1));
-            }
+    if is_primary(backend) {
+        update_connection_state(ConnectionState::Connecting);
+    }
+
+    let mut retry = Backoff::new(RECONNECT_BASE, RECONNECT_MAX);
+    let mut last_error = String::new();
+
+    let client = loop {
+        if !backend.socket.exists() {
+            println!("waiting for elephant at {}...", backend.socket.display());
+            wait_for_socket(&backend.socket);
         }
-    };
-    *CONN.lock().unwrap() = Some(conn);
 
-    let menuconn = loop {
-        match UnixStream::connect(&socket_path) {
-            Ok(conn) => break conn,
+        match connect(backend) {
+            Ok(client) => break client,
+            Err(ConnectError::Incompatible(e)) => {
+                let error = format!("elephant at {}: {e}", backend.socket.display());
+                eprintln!("{error}");
+
+                set_backend_state(backend, ConnectionState::Incompatible);
+                show_error(&error);
+
+                // retrying is pointless until a different elephant shows up
+                wait_for_restart(&backend.socket);
+
+                hide_error(&error);
+                retry = Backoff::new(RECONNECT_BASE, RECONNECT_MAX);
+            }
             Err(e) => {
-                println!("Failed to connect to menu: {e}. Retrying in 1 second...");
-                thread::sleep(Duration::from_secs(1));
+                // retries are quiet unless something new goes wrong
+                let error = e.to_string();
+
+                if error != last_error {
+                    eprintln!(
+                        "failed to connect to elephant at {}: {error}",
+                        backend.socket.display()
+                    );
+                    last_error = error;
+                }
+
+                set_backend_state(backend, ConnectionState::Failed);
+                thread::sleep(retry.next_delay());
             }
         }
     };
 
-    *MENUCONN.lock().unwrap() = Some(menuconn);
+    backend.reset_qids();
+    *backend.client.lock().unwrap() = Some(client);
 
-    if PROVIDERS.get().unwrap().get("bluetooth").is_some() {
-        let bluetoothconn = loop {
-            match UnixStream::connect(&socket_path) {
-                Ok(conn) => break conn,
-                Err(e) => {
-                    println!("Failed to connect to menu: {e}. Retrying in 1 second...");
-                    thread::sleep(Duration::from_secs(1));
-                }
-            }
-        };
+    set_backend_state(backend, ConnectionState::Connected);
+}
 
-        *BLUETOOTHCONN.lock().unwrap() = Some(bluetoothconn);
-        subscribe_bluetooth().unwrap();
+fn set_backend_state(backend: &Arc<Backend>, state: ConnectionState) {
+    if backend.state() != state {
+        match state {
+            ConnectionState::Connected => {
+                println!("connected to elephant at {}.", backend.socket.display())
+            }
+            ConnectionState::Disconnected => {
+                println!(
+                    "disconnected from elephant at {}.",
+                    backend.socket.display()
+                )
+            }
+            _ => {}
+        }
     }
 
-    subscribe_menu().unwrap();
-    start_listening();
+    backend.set_state(state);
 
-    glib::idle_add_once(|| {
-        with_window(|w| {
-            w.elephant_hint.set_visible(false);
-            w.scroll.set_visible(true);
+    if is_primary(backend) {
+        update_connection_state(state);
+    }
+}
 
-            set_is_connected(true);
+fn show_error(error: &str) {
+    set_error(error.to_string());
 
-            if let Some(input) = &w.input {
-                input.emit_by_name::<()>("changed", &[]);
-            }
+    let error = error.to_string();
+    glib::idle_add_once(move || {
+        with_window(|w| {
+            w.error.set_text(&error);
+            w.error.set_visible(true);
         });
     });
+}
 
-    set_is_connecting(false);
+fn hide_error(error: &str) {
+    // don't hide anything else that went wrong in the meantime
+    if get_error() != error {
+        return;
+    }
 
-    println!("connected.");
+    set_error(String::new());
+    glib::idle_add_once(|| with_window(|w| w.error.set_visible(false)));
+}
 
-    Ok(())
+fn show_provider_error(err: &ErrorResponse) {
+    let error = if err.provider.is_empty() {
+        format!("elephant: {}", err.message)
+    } else {
+        format!("{}: {}", err.provider, err.message)
+    };
+
+    eprintln!("{error}");
+    show_error(&error);
+    *PROVIDER_ERROR.lock().unwrap() = error.clone();
+
+    let timeout = get_config().elephant.error_timeout;
+
+    if timeout > 0 {
+        glib::timeout_add_once(Duration::from_millis(timeout), move || hide_error(&error));
+    }
 }
 
-fn start_listening() {
-    thread::spawn(|| {
-        if let Err(e) = listen_loop() {
-            handle_disconnect();
-            eprintln!("Listen loop error: {e}");
+fn update_connection_state(state: ConnectionState) {
+    if get_connection_state() == state {
+        return;
+    }
+
+    set_connection_state(state);
+    glib::idle_add_once(move || apply_connection_state(state));
+}
+
+pub fn apply_connection_state(state: ConnectionState) {
+    with_window(|w| {
+        ConnectionState::ALL
+            .iter()
+            .for_each(|s| w.window.remove_css_class(s.css_class()));
+        w.window.add_css_class(state.css_class());
+
+        if is_dmenu() {
+            return;
         }
-    });
 
-    thread::spawn(|| {
-        if let Err(e) = listen_menus_loop() {
-            handle_disconnect();
-            eprintln!("Listen menu_loop error: {e}");
+        let connected = state == ConnectionState::Connected;
+
+        w.elephant_hint.set_visible(!connected);
+        w.scroll.set_visible(connected);
+
+        if connected && let Some(input) = &w.input {
+            input.emit_by_name::<()>("changed", &[]);
         }
     });
+}
 
-    if PROVIDERS.get().unwrap().get("bluetooth").is_some() {
-        thread::spawn(|| {
-            if let Err(e) = listen_bluetooth_loop() {
-                handle_disconnect();
-                eprintln!("Listen menu_loop error: {e}");
+fn connect(backend: &Arc<Backend>) -> Result<ElephantClient, ConnectError> {
+    let responses = backend.clone();
+    let disconnects = backend.clone();
+
+    let mut client = ElephantClient::connect(
+        &backend.socket,
+        move |frame| handle_response(&responses, frame),
+        move |e| {
+            eprintln!(
+                "elephant connection lost at {}: {e}",
+                disconnects.socket.display()
+            );
+            handle_disconnect(&disconnects);
+        },
+    )?;
+
+    backend.set_announced(
+        client
+            .hello()
+            .providers
+            .iter()
+            .map(|p| p.name.clone())
+            .collect(),
+    );
+
+    if !client.hello().supports(FRAME_SUBSCRIBE) {
+        return Ok(client);
+    }
+
+    let providers = PROVIDERS.get().unwrap();
+    let backends = backends();
+
+    for subscription in get_config().subscriptions.iter().filter(|s| {
+        (!s.if_installed || providers.contains_key(&s.provider))
+            && Arc::ptr_eq(&backends[route(backends, &s.provider)], backend)
+    }) {
+        let mut req = SubscribeRequest::new();
+        req.provider = subscription.provider.clone();
+        req.interval = subscription.interval;
+        req.query = subscription.query.clone();
+
+        client.subscribe(req, move |frame| {
+            if let Response::Subscription(resp) = frame {
+                glib::idle_add_once(move || handle_subscription(subscription, resp.value));
             }
-        });
+        })?;
     }
+
+    Ok(client)
+}
+
+thread_local! {
+    // css class each subscription last put on the window, by provider
+    static SUBSCRIPTION_CLASSES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
 }
 
-fn listen_bluetooth_loop() -> Result<(), Box<dyn std::error::Error>> {
-    let mut conn_guard = BLUETOOTHCONN.lock().unwrap();
-    let conn = conn_guard.as_mut().ok_or("Connection not initialized")?;
+fn handle_subscription(subscription: &Subscription, value: String) {
+    let Some(effect) = subscription
+        .effects
+        .get(&value)
+        .or_else(|| subscription.effects.get("*"))
+    else {
+        return;
+    };
 
-    let mut conn_clone = conn.try_clone()?;
-    drop(conn_guard);
+    let expand = |s: &str| s.replace("%VALUE%", &value);
+
+    with_window(|w| {
+        if let Some(text) = &effect.placeholder
+            && let Some(p) = &w.placeholder
+        {
+            p.set_text(&expand(text));
+            p.set_visible(true);
+            w.scroll.set_visible(false);
+        }
+
+        if let Some(class) = &effect.css_class {
+            let class = expand(class);
+
+            SUBSCRIPTION_CLASSES.with_borrow_mut(|classes| {
+                if let Some(previous) = classes.insert(subscription.provider.clone(), class.clone())
+                {
+                    w.window.remove_css_class(&previous);
+                }
+            });
 
-    let mut reader = BufReader::new(&mut conn_clone);
+            w.window.add_css_class(&class);
+        }
+    });
 
-    loop {
-        let mut header = [0u8; 5];
-        reader.read_exact(&mut header)?;
+    if let Some(provider) = &effect.open {
+        set_provider(expand(provider));
 
-        match header[0] {
-            0 => {
-                let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+        with_window(|w| {
+            set_input_text("");
+            w.window.present();
+        });
 
-                let mut payload = vec![0u8; length as usize];
-                reader.read_exact(&mut payload)?;
+        set_is_visible(true);
+    } else if effect.refresh {
+        with_window(|w| {
+            if let Some(input) = &w.input {
+                input.emit_by_name::<()>("changed", &[]);
+            }
+        });
+    }
+}
 
-                let mut resp = SubscribeResponse::new();
-                resp.merge_from_bytes(&payload)?;
+fn handle_response(backend: &Arc<Backend>, frame: Response) {
+    match frame {
+        Response::Done => {
+            glib::idle_add_once(|| {
+                flush();
+                set_keybind_hint();
+                handle_preview();
+            });
+        }
+        Response::Clear => {
+            glib::idle_add_once(|| with_window(show_current_query));
+        }
+        // qid 0 isn't tied to a query, f.e. a frame that couldn't be read
+        Response::Error(err)
+            if err.qid == 0 || backend.is_current(err.qid, None, &err.provider) =>
+        {
+            show_provider_error(&err);
+        }
+        Response::Error(_) | Response::Ignored | Response::Subscription(_) | Response::Hello(_) => {
+        }
+        Response::AsyncDone => {
+            glib::idle_add_once(move || match get_async_after() {
+                Some(AfterAction::AsyncReload) => {
+                    with_window(|w| {
+                        if let Some(input) = &w.input {
+                            set_input_text(&input.text());
+                        }
+                    });
 
-                glib::idle_add_once(move || {
+                    set_async_after(None);
+                }
+                Some(AfterAction::AsyncClearReload) => {
                     with_window(|w| {
-                        if let Some(p) = &w.placeholder {
-                            match resp.value.as_str() {
-                                "bluetooth:remove" => p.set_text("Removing..."),
-                                "bluetooth:connect" => p.set_text("Connecting..."),
-                                "bluetooth:disconnect" => p.set_text("Disconnecting..."),
-                                "bluetooth:trust" => p.set_text("Trusting..."),
-                                "bluetooth:untrust" => p.set_text("Un-Trusting..."),
-                                "bluetooth:pair" => p.set_text("Pairing..."),
-                                "bluetooth:find" => p.set_text("Scanning..."),
-                                _ => (),
+                        if let Some(input) = &w.input {
+                            if input.text().is_empty() {
+                                input.emit_by_name::<()>("changed", &[]);
+                            } else {
+                                set_input_text(&get_current_prefix());
                             }
-
-                            p.set_visible(true);
-                            w.scroll.set_visible(false);
                         }
                     });
-                });
+
+                    set_async_after(None);
+                }
+                _ => (),
+            });
+        }
+        Response::Update(ref resp) | Response::Item(ref resp) => {
+            backend.saw_qid(resp.qid);
+
+            if is_current(backend, resp) {
+                buffer(backend, frame);
             }
-            _ => continue,
         }
-    }
+    };
 }
 
-fn listen_menus_loop() -> Result<(), Box<dyn std::error::Error>> {
-    let mut conn_guard = MENUCONN.lock().unwrap();
-    let conn = conn_guard.as_mut().ok_or("Connection not initialized")?;
+fn is_current(backend: &Backend, resp: &QueryResponse) -> bool {
+    let provider = resp.item.as_ref().map_or("", |i| i.provider.as_str());
+    backend.is_current(resp.qid, Some(&resp.query), provider)
+}
 
-    let mut conn_clone = conn.try_clone()?;
-    drop(conn_guard);
+// results are collected here and handed to the list in one go, so the list
+// and everything listening to it only update once per batch
+fn buffer(backend: &Arc<Backend>, frame: Response) {
+    PENDING.lock().unwrap().push((backend.clone(), frame));
 
-    let mut reader = BufReader::new(&mut conn_clone);
+    if !FLUSH_SCHEDULED.swap(true, atomic::Ordering::SeqCst) {
+        glib::idle_add_once(flush);
+    }
+}
 
-    loop {
-        let mut header = [0u8; 5];
-        reader.read_exact(&mut header)?;
+fn flush() {
+    FLUSH_SCHEDULED.store(false, atomic::Ordering::SeqCst);
 
-        match header[0] {
-            0 => {
-                let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+    let pending = mem::take(&mut *PENDING.lock().unwrap());
+    let mut added: Vec<QueryResponse> = Vec::new();
 
-                let mut payload = vec![0u8; length as usize];
-                reader.read_exact(&mut payload)?;
+    // a newer query might have been issued while these were queued
+    for (backend, frame) in pending {
+        // the same text went out again, its first answers have to go
+        if let Some(qid) = backend.take_superseded() {
+            with_window(|w| remove_answers(w, &backend, qid));
+        }
 
-                let mut resp = SubscribeResponse::new();
-                resp.merge_from_bytes(&payload)?;
+        match frame {
+            Response::Item(resp) if is_current(&backend, &resp) => added.push(resp),
+            Response::Update(resp) if is_current(&backend, &resp) => {
+                match added.iter().position(|a| same_item(a, &resp)) {
+                    Some(i) if is_deleted(&resp) => {
+                        added.remove(i);
+                    }
+                    Some(i) => added[i] = resp,
+                    None => update_existing_item(resp),
+                }
+            }
+            _ => {}
+        }
+    }
 
-                glib::idle_add_once(move || {
-                    set_provider(resp.value);
+    if added.is_empty() {
+        return;
+    }
 
-                    with_window(|w| {
-                        set_input_text("");
-                        w.window.present();
-                    });
+    // stable, so equal scores keep the order they arrived in
+    added.sort_by(|a, b| weighted_score(b).total_cmp(&weighted_score(a)));
 
-                    set_is_visible(true);
-                });
-            }
-            _ => continue,
+    with_window(|w| {
+        let items = &w.items;
+        let n_items = items.n_items();
+
+        if start_current_query() {
+            let objects: Vec<QueryResponseObject> =
+                added.into_iter().map(QueryResponseObject::new).collect();
+
+            items.splice(0, n_items, &objects);
+            return;
         }
-    }
-}
 
-fn listen_loop() -> Result<(), Box<dyn std::error::Error>> {
-    let mut conn_guard = CONN.lock().unwrap();
-    let conn = conn_guard.as_mut().ok_or("Connection not initialized")?;
+        let existing: Vec<QueryResponseObject> = items
+            .iter()
+            .flatten()
+            .map(Object::downcast::<QueryResponseObject>)
+            .filter_map(Result::ok)
+            .collect();
 
-    let mut conn_clone = conn.try_clone()?;
-    drop(conn_guard);
+        // everything from the first displaced item on is replaced in one splice
+        let best = weighted_score(&added[0]);
+        let start = existing.partition_point(|o| weighted_score(&o.response()) >= best);
 
-    let mut reader = BufReader::new(&mut conn_clone);
+        let mut added = added.into_iter().peekable();
+        let mut tail: Vec<QueryResponseObject> = Vec::new();
 
-    loop {
-        let mut header = [0u8; 5];
-        reader.read_exact(&mut header)?;
+        for obj in &existing[start..] {
+            let score = weighted_score(&obj.response());
 
-        match header[0] {
-            255 => {
-                glib::idle_add_once(|| {
-                    set_keybind_hint();
-                    handle_preview();
-                });
-            }
-            254 => {
-                glib::idle_add_once(clear_items);
+            while let Some(resp) = added.next_if(|r| weighted_score(r) > score) {
+                tail.push(QueryResponseObject::new(resp));
             }
-            230 => {}
-            2 => {
-                glib::idle_add_once(move || match get_async_after() {
-                    Some(AfterAction::AsyncReload) => {
-                        with_window(|w| {
-                            if let Some(input) = &w.input {
-                                set_input_text(&input.text());
-                            }
-                        });
 
-                        set_async_after(None);
-                    }
-                    Some(AfterAction::AsyncClearReload) => {
-                        with_window(|w| {
-                            if let Some(input) = &w.input {
-                                if input.text().is_empty() {
-                                    input.emit_by_name::<()>("changed", &[]);
-                                } else {
-                                    set_input_text(&get_current_prefix());
-                                }
-                            }
-                        });
+            tail.push(obj.clone());
+        }
 
-                        set_async_after(None);
-                    }
-                    _ => (),
-                });
-            }
-            _ => {
-                let length = u32::from_be_bytes(header[1..].try_into().unwrap());
+        tail.extend(added.map(QueryResponseObject::new));
 
-                let mut payload = vec![0u8; length as usize];
-                reader.read_exact(&mut payload)?;
+        items.splice(start as u32, n_items - start as u32, &tail);
+    });
+}
 
-                let mut resp = QueryResponse::new();
-                resp.merge_from_bytes(&payload)?;
+// drops what `backend` answered with `qid`
+fn remove_answers(w: &WindowData, backend: &Backend, qid: i32) {
+    let backends = backends();
+    let items = &w.items;
 
-                glib::idle_add_once(move || handle_response(resp, header[0]));
-            }
+    for i in (0..items.n_items()).rev() {
+        let Some(obj) = items.item(i).and_downcast::<QueryResponseObject>() else {
+            continue;
         };
+
+        let resp = obj.response();
+        let provider = resp.item.as_ref().map_or("", |i| i.provider.as_str());
+
+        if resp.qid == qid && std::ptr::eq(&*backends[route(backends, provider)], backend) {
+            items.remove(i);
+        }
     }
 }
 
-fn handle_response(resp: QueryResponse, header_type: u8) {
-    let function = match header_type {
-        1 => update_existing_item,
-        _ => add_new_item,
-    };
+fn weighted_score(resp: &QueryResponse) -> f64 {
+    resp.item.as_ref().map_or(0.0, |i| {
+        f64::from(i.score) * get_config().providers.weight(&i.provider)
+    })
+}
 
-    function(resp)
+fn same_item(a: &QueryResponse, b: &QueryResponse) -> bool {
+    match (a.item.as_ref(), b.item.as_ref()) {
+        (Some(a), Some(b)) => a.identifier == b.identifier,
+        _ => false,
+    }
 }
 
-fn clear_items() {
-    with_window(|w| w.items.remove_all());
+fn is_deleted(resp: &QueryResponse) -> bool {
+    resp.item.as_ref().is_some_and(|i| i.text == "%DELETE%")
+}
+
+// true for the first batch of results of the current query, which replaces
+// whatever the list still shows
+fn start_current_query() -> bool {
+    let current = QUERY_GENERATION.load(atomic::Ordering::SeqCst);
+
+    if SHOWN_GENERATION.swap(current, atomic::Ordering::SeqCst) == current {
+        return false;
+    }
+
     crate::preview::clear_all_caches();
+    true
+}
+
+// a clear only empties the list if no backend answered the current query yet
+fn show_current_query(w: &WindowData) {
+    if start_current_query() {
+        w.items.remove_all();
+    }
 }
 
 fn update_existing_item(resp: QueryResponse) {
@@ -426,10 +608,26 @@ fn update_existing_item(resp: QueryResponse) {
                 continue;
             }
 
-            if resp_item.text == "%DELETE%" {
+            let score = weighted_score(&resp);
+
+            if is_deleted(&resp) {
                 items.remove(i);
-            } else {
+            } else if score == weighted_score(&existing) {
                 items.splice(i, 1, &[crate::QueryResponseObject::new(resp)]);
+            } else {
+                // moved to where its new score belongs, flush relies on the order
+                items.remove(i);
+
+                let pos = (0..items.n_items())
+                    .find(|&j| {
+                        items
+                            .item(j)
+                            .and_downcast::<QueryResponseObject>()
+                            .is_some_and(|o| weighted_score(&o.response()) < score)
+                    })
+                    .unwrap_or(items.n_items());
+
+                items.insert(pos, &QueryResponseObject::new(resp));
             }
             break;
         }
@@ -438,25 +636,26 @@ fn update_existing_item(resp: QueryResponse) {
     });
 }
 
-fn add_new_item(resp: QueryResponse) {
-    with_window(|w| {
-        let items = &w.items;
-        let n_items = items.n_items();
+fn debounce_query(text: &str) {
+    if let Some(pending) = QUERY_DEBOUNCE.take() {
+        pending.remove();
+    }
 
-        if let Some(n_items) = n_items.checked_sub(1)
-            && let Some(last_obj) = items
-                .item(n_items)
-                .and_downcast::<crate::QueryResponseObject>()
-        {
-            let last_resp = last_obj.response();
+    let debounce = get_config().query_debounce;
 
-            if resp.qid > last_resp.qid {
-                items.remove_all();
-            }
-        }
+    if debounce == 0 {
+        query(text);
+        return;
+    }
 
-        items.append(&crate::QueryResponseObject::new(resp));
+    let text = text.to_string();
+
+    let source = glib::timeout_add_local_once(Duration::from_millis(debounce), move || {
+        QUERY_DEBOUNCE.set(None);
+        query(&text);
     });
+
+    QUERY_DEBOUNCE.set(Some(source));
 }
 
 fn query(text: &str) {
@@ -536,36 +735,106 @@ fn query(text: &str) {
         req.maxresults = cfg.providers.max_results;
     }
 
-    let mut buffer = vec![0];
-    let length = req.compute_size() as u32;
-    buffer.extend_from_slice(&length.to_be_bytes());
-    req.write_to_vec(&mut buffer).unwrap();
+    send_query(req);
+}
 
-    if let Some(conn) = CONN.lock().unwrap().as_mut() {
-        match conn.write_all(&buffer) {
-            Err(_) => {
-                handle_disconnect();
-            }
-            _ => (),
+// every backend gets the query with just the providers it serves
+fn send_query(req: QueryRequest) {
+    if !has_elephant() {
+        query_builtin(req);
+        return;
+    }
+
+    let error = mem::take(&mut *PROVIDER_ERROR.lock().unwrap());
+    if !error.is_empty() {
+        hide_error(&error);
+    }
+
+    let backends = backends();
+
+    QUERY_GENERATION.fetch_add(1, atomic::Ordering::SeqCst);
+
+    if req.providers.is_empty() {
+        backends.iter().skip(1).for_each(|b| b.issue(None));
+        backends[0].issue(Some(&req));
+        send(&backends[0], Request::Query(req));
+        return;
+    }
+
+    for (i, backend) in backends.iter().enumerate() {
+        let mut routed = req.clone();
+        routed.providers.retain(|p| route(backends, p) == i);
+
+        if routed.providers.is_empty() {
+            backend.issue(None);
+        } else {
+            backend.issue(Some(&routed));
+            send(backend, Request::Query(routed));
         }
     }
 }
 
-fn handle_disconnect() {
-    set_is_connected(false);
+fn query_builtin(req: QueryRequest) {
+    let providers = PROVIDERS.get().unwrap();
 
-    thread::spawn(|| {
-        glib::idle_add_once(|| {
-            with_window(|w| {
-                w.elephant_hint.set_visible(true);
-                w.scroll.set_visible(false);
-            });
-        });
+    let items: Vec<Item> = req
+        .providers
+        .iter()
+        .filter_map(|p| providers.get(p))
+        .filter_map(|p| p.query(&req.query, req.exactsearch))
+        .flatten()
+        .collect();
+
+    let mut responses: Vec<QueryResponse> = items
+        .into_iter()
+        .map(|item| {
+            let mut resp = QueryResponse::new();
+            resp.query = req.query.clone();
+            resp.item = MessageField::some(item);
+            resp
+        })
+        .collect();
 
-        while let Err(err) = init_socket() {
-            println!("{err}");
-        }
+    responses.sort_by(|a, b| weighted_score(b).total_cmp(&weighted_score(a)));
+    responses.truncate(usize::try_from(req.maxresults).unwrap_or_default());
+
+    let items: Vec<QueryResponseObject> = responses
+        .into_iter()
+        .map(QueryResponseObject::new)
+        .collect();
+
+    with_window(|w| {
+        w.items.remove_all();
+        w.items.extend_from_slice(&items);
     });
+
+    set_keybind_hint();
+    handle_preview();
+}
+
+fn send_activate(req: ActivateRequest) {
+    let backends = backends();
+    send(
+        &backends[route(backends, &req.provider)],
+        Request::Activate(req),
+    );
+}
+
+fn send(backend: &Arc<Backend>, req: Request) {
+    if backend.send(&req).is_err() {
+        handle_disconnect(backend);
+    }
+}
+
+fn handle_disconnect(backend: &Arc<Backend>) {
+    if !backend.try_disconnect() {
+        return;
+    }
+
+    set_backend_state(backend, ConnectionState::Disconnected);
+
+    let backend = backend.clone();
+    thread::spawn(move || connect_backend(&backend));
 }
 
 pub fn clipboard_disable_images_only() {
@@ -573,20 +842,69 @@ pub fn clipboard_disable_images_only() {
     req.action = "disable_images_only".to_string();
     req.provider = "clipboard".to_string();
 
-    let mut buffer = vec![1];
-    let length = req.compute_size() as u32;
-    buffer.extend_from_slice(&length.to_be_bytes());
-    req.write_to_vec(&mut buffer).unwrap();
+    send_activate(req);
+}
+
+// in the --format asked for. With --multi all marked lines in input order, the
+// selected one if none are. Without any the query stands in.
+pub fn dmenu_result(selected: Option<&Item>, query: &str, action: &str) -> String {
+    let mut items: Vec<Item> = if is_dmenu_multi() {
+        dmenu_list::marked_items()
+    } else {
+        Vec::new()
+    };
 
-    let mut conn_guard = CONN.lock().unwrap();
+    if items.is_empty() {
+        items.extend(selected.cloned());
+    }
 
-    if let Some(conn) = conn_guard.as_mut() {
-        match conn.write_all(&buffer) {
-            Err(_) => {
-                handle_disconnect();
-            }
-            _ => (),
-        }
+    items.sort_by_key(|i| Reverse(i.score));
+
+    let lines: Vec<String> = match get_dmenu_format() {
+        Format::Query => vec![query.to_string()],
+        Format::Text if items.is_empty() => vec![query.to_string()],
+        Format::Text => items.iter().map(dmenu_output).collect(),
+        Format::Index if items.is_empty() => vec!["-1".to_string()],
+        Format::Index => items.iter().map(|i| dmenu::index(i).to_string()).collect(),
+        Format::Json if items.is_empty() => vec![
+            serde_json::json!({
+                "index": null,
+                "text": query,
+                "info": null,
+                "query": query,
+                "action": action,
+            })
+            .to_string(),
+        ],
+        Format::Json => items
+            .iter()
+            .map(|i| {
+                serde_json::json!({
+                    "index": dmenu::index(i),
+                    "text": dmenu_output(i),
+                    "info": dmenu_list::info(i),
+                    "query": query,
+                    "action": action,
+                })
+                .to_string()
+            })
+            .collect(),
+    };
+
+    lines.join("\n")
+}
+
+// the whole input line, or just the --accept-nth fields of it
+fn dmenu_output(item: &Item) -> String {
+    let line = if item.identifier.is_empty() {
+        &item.text
+    } else {
+        &item.identifier
+    };
+
+    match get_dmenu_accept_nth() {
+        Some(fields) => fields.select(line, &get_dmenu_delimiter()),
+        None => line.clone(),
     }
 }
 
@@ -605,10 +923,12 @@ pub fn activate(item_option: Option<QueryResponse>, provider: &str, query: &str,
     if let Some(item) = item_option {
         match provider {
             "dmenu" => {
+                let text = dmenu_result(item.item.as_ref(), query, &action.action);
+
                 if is_service() {
-                    send_message(item.item.text.clone());
+                    send_message(text);
                 } else {
-                    println!("{}", item.item.text.clone());
+                    println!("{text}");
                 }
                 return;
             }
@@ -631,6 +951,14 @@ pub fn activate(item_option: Option<QueryResponse>, provider: &str, query: &str,
                 }
                 req.provider = item.item.provider.clone();
                 req.identifier = item.item.identifier.clone();
+
+                if !has_elephant() {
+                    if let Some(p) = PROVIDERS.get().unwrap().get(&req.provider) {
+                        p.activate(&item.item, &req.action);
+                    }
+
+                    return;
+                }
             }
         }
     } else if provider.starts_with("menus:") {
@@ -650,69 +978,202 @@ pub fn activate(item_option: Option<QueryResponse>, provider: &str, query: &str,
 
     req.query = query.to_string();
 
-    let mut buffer = vec![1];
-    let length = req.compute_size() as u32;
-    buffer.extend_from_slice(&length.to_be_bytes());
-    req.write_to_vec(&mut buffer).unwrap();
+    send_activate(req);
+}
 
-    let mut conn_guard = CONN.lock().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elephant::mock::{Fixtures, MockElephant};
+    use crate::providers::{Provider, default_provider::DefaultProvider, dmenu::Dmenu};
+    use std::collections::HashMap;
+    use std::time::Instant;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn setup() {
+        crate::state::init_test_state();
+        crate::state::set_has_elephant(true);
+        crate::config::load_defaults();
+
+        PROVIDERS.get_or_init(|| {
+            let mut providers: HashMap<String, Box<dyn Provider>> = HashMap::new();
+            providers.insert("dmenu".to_string(), Box::new(Dmenu::new()));
+
+            ["desktopapplications", "calc", "menus", "bluetooth"]
+                .into_iter()
+                .for_each(|p| {
+                    providers.insert(p.to_string(), Box::new(DefaultProvider::new(p.to_string())));
+                });
 
-    if let Some(conn) = conn_guard.as_mut() {
-        match conn.write_all(&buffer) {
-            Err(_) => {
-                handle_disconnect();
-            }
-            _ => (),
-        }
+            providers
+        });
     }
-}
 
-fn subscribe_menu() -> Result<(), Box<dyn std::error::Error>> {
-    let mut req = SubscribeRequest::new();
-    req.provider = "menus".to_string();
+    fn item(identifier: &str, text: &str) -> QueryResponse {
+        let mut item = Item::new();
+        item.identifier = identifier.to_string();
+        item.text = text.to_string();
+        item.provider = "desktopapplications".to_string();
+        item.actions = vec!["start".to_string()];
 
-    let mut buffer = vec![2];
-    let length = req.compute_size() as u32;
-    buffer.extend_from_slice(&length.to_be_bytes());
-    req.write_to_vec(&mut buffer).unwrap();
+        let mut resp = QueryResponse::new();
+        resp.item = MessageField::some(item);
+        resp
+    }
 
-    {
-        let mut conn_guard = MENUCONN.lock().unwrap();
+    fn pending_queries() -> Vec<String> {
+        PENDING
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(_, frame)| match frame {
+                Response::Item(resp) => Some(resp.query.clone()),
+                _ => None,
+            })
+            .collect()
+    }
 
-        if let Some(conn) = conn_guard.as_mut() {
-            conn.write_all(&buffer)?;
-        } else {
-            return Err("Connection not available".into());
-        }
+    fn subscriptions(requests: &[Request], provider: &str) -> usize {
+        requests
+            .iter()
+            .filter(|r| matches!(r, Request::Subscribe(s) if s.provider == provider))
+            .count()
     }
 
-    Ok(())
-}
+    // the connections are process wide, so everything runs against the same
+    // mocks in a single test
+    #[test]
+    fn talks_to_elephant() {
+        setup();
+
+        let mut fixtures = Fixtures::default();
+        fixtures
+            .queries
+            .insert("fire".to_string(), vec![item("firefox.desktop", "Firefox")]);
+        fixtures
+            .queries
+            .insert("fox".to_string(), vec![item("firefox.desktop", "Firefox")]);
+        fixtures.queries.insert(
+            "firefox".to_string(),
+            vec![item("firefox.desktop", "Firefox")],
+        );
+        fixtures.subscriptions.insert(
+            "bluetooth".to_string(),
+            vec!["bluetooth:connect".to_string()],
+        );
+
+        let mock = MockElephant::start(fixtures);
+        let work = MockElephant::start(Fixtures::default());
+
+        assert!(
+            BACKENDS
+                .set(vec![
+                    Arc::new(Backend::new(mock.socket_path().to_path_buf(), Vec::new())),
+                    Arc::new(Backend::new(
+                        work.socket_path().to_path_buf(),
+                        vec!["calc".to_string()],
+                    )),
+                ])
+                .is_ok()
+        );
+
+        init_socket();
+
+        assert!(mock.wait_for(TIMEOUT, |r| subscriptions(r, "menus") == 1
+            && subscriptions(r, "bluetooth") == 1));
+        assert!(work.wait_for(TIMEOUT, |_| work.accepted() == 1));
+
+        query("fire");
+
+        // every backend only gets asked about its own providers
+        let mut primary = get_config().providers.default.clone();
+        primary.retain(|p| p != "calc");
+
+        assert!(mock.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Query(q) if q.query == "fire"
+                && q.providers == primary
+                && q.maxresults == get_config().providers.max_results
+        ))));
+        assert!(work.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Query(q) if q.query == "fire" && q.providers == vec!["calc".to_string()]
+        ))));
+
+        // prefixes pick the provider and are stripped from the query
+        query("=1+1");
+
+        assert!(work.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Query(q) if q.query == "1+1" && q.providers == vec!["calc".to_string()]
+        ))));
+        assert!(
+            !mock
+                .requests()
+                .iter()
+                .any(|r| matches!(r, Request::Query(q) if q.query == "1+1"))
+        );
 
-fn subscribe_bluetooth() -> Result<(), Box<dyn std::error::Error>> {
-    let mut req = SubscribeRequest::new();
-    req.provider = "bluetooth".to_string();
+        // both are sent before either is answered, only the last one counts
+        mock.hold();
+        PENDING.lock().unwrap().clear();
 
-    let mut buffer = vec![2];
-    let length = req.compute_size() as u32;
-    buffer.extend_from_slice(&length.to_be_bytes());
-    req.write_to_vec(&mut buffer).unwrap();
+        query("fox");
+        query("firefox");
 
-    {
-        let mut conn_guard = BLUETOOTHCONN.lock().unwrap();
+        assert!(mock.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Query(q) if q.query == "firefox"
+        ))));
 
-        if let Some(conn) = conn_guard.as_mut() {
-            conn.write_all(&buffer)?;
-        } else {
-            return Err("Connection not available".into());
+        mock.release();
+
+        let start = Instant::now();
+        while !pending_queries().contains(&"firefox".to_string()) && start.elapsed() < TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
         }
-    }
 
-    Ok(())
-}
+        assert!(pending_queries().contains(&"firefox".to_string()));
+        assert!(!pending_queries().contains(&"fox".to_string()));
+
+        let action = Action {
+            action: "start".to_string(),
+            global: None,
+            default: Some(true),
+            bind: None,
+            after: None,
+            label: None,
+        };
 
-fn wait_for_file(path: &str) {
-    while !Path::new(path).exists() {
-        thread::sleep(Duration::from_millis(10));
+        activate(
+            Some(item("firefox.desktop", "Firefox")),
+            "desktopapplications",
+            "fire",
+            &action,
+        );
+
+        assert!(mock.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Activate(a) if a.identifier == "firefox.desktop"
+                && a.provider == "desktopapplications"
+                && a.action == "start"
+                && a.query == "fire"
+        ))));
+
+        mock.disconnect_all();
+
+        assert!(
+            mock.wait_for(TIMEOUT, |r| subscriptions(r, "menus") >= 2
+                && subscriptions(r, "bluetooth") >= 2),
+            "didn't reconnect after elephant went away"
+        );
+
+        query("after reconnect");
+
+        assert!(mock.wait_for(TIMEOUT, |r| r.iter().any(|r| matches!(
+            r,
+            Request::Query(q) if q.query == "after reconnect"
+        ))));
     }
 }
@@ -0,0 +1,250 @@
+// An elephant instance walker talks to and the providers it serves.
+
+use super::{ElephantClient, Request};
+use crate::protos::generated_proto::query::QueryRequest;
+use crate::state::ConnectionState;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub struct Backend {
+    pub socket: PathBuf,
+    // empty for the primary backend, which serves everything not claimed elsewhere
+    pub providers: Vec<String>,
+    // what elephant itself said it serves during the handshake
+    announced: RwLock<Vec<String>>,
+    pub client: Mutex<Option<ElephantClient>>,
+    state: Mutex<ConnectionState>,
+    // qids only go up, anything below `stale_qid` was answered before the last query went out
+    latest_qid: AtomicI32,
+    stale_qid: AtomicI32,
+    issued: Mutex<Option<Issued>>,
+    // answers replaced by those to the same text sent again, 0 if none
+    superseded_qid: AtomicI32,
+}
+
+// the last query sent, its qid is known once the first answer arrives
+struct Issued {
+    query: String,
+    providers: Vec<String>,
+    qid: i32,
+}
+
+impl Backend {
+    pub fn new(socket: PathBuf, providers: Vec<String>) -> Self {
+        Self {
+            socket,
+            providers,
+            announced: RwLock::new(Vec::new()),
+            client: Mutex::new(None),
+            state: Mutex::new(ConnectionState::Disconnected),
+            latest_qid: AtomicI32::new(0),
+            stale_qid: AtomicI32::new(0),
+            issued: Mutex::new(None),
+            superseded_qid: AtomicI32::new(0),
+        }
+    }
+
+    pub fn serves(&self, provider: &str) -> bool {
+        self.providers
+            .iter()
+            .chain(self.announced.read().unwrap().iter())
+            .any(|p| is_provider(p, provider))
+    }
+
+    pub fn set_announced(&self, providers: Vec<String>) {
+        *self.announced.write().unwrap() = providers;
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set_state(&self, state: ConnectionState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    // only one connection attempt may run per backend at a time
+    pub fn try_start_connecting(&self) -> bool {
+        self.transition(ConnectionState::Disconnected, ConnectionState::Connecting)
+    }
+
+    // a failed send and the reader both notice a dead socket, the first one wins
+    pub fn try_disconnect(&self) -> bool {
+        self.transition(ConnectionState::Connected, ConnectionState::Disconnected)
+    }
+
+    fn transition(&self, from: ConnectionState, to: ConnectionState) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if *state != from {
+            return false;
+        }
+
+        *state = to;
+        true
+    }
+
+    pub fn send(&self, req: &Request) -> io::Result<()> {
+        match self.client.lock().unwrap().as_mut() {
+            Some(client) => client.send(req),
+            None => Ok(()),
+        }
+    }
+
+    pub fn saw_qid(&self, qid: i32) {
+        self.latest_qid.fetch_max(qid, Ordering::SeqCst);
+    }
+
+    // None if this backend wasn't asked, then nothing is current
+    pub fn issue(&self, req: Option<&QueryRequest>) {
+        self.stale_qid
+            .store(self.latest_qid.load(Ordering::SeqCst) + 1, Ordering::SeqCst);
+
+        *self.issued.lock().unwrap() = req.map(|req| Issued {
+            query: req.query.clone(),
+            providers: req.providers.clone(),
+            qid: 0,
+        });
+    }
+
+    // true if a response answers the last query sent. A response without its
+    // query text can't be the one to pick the qid.
+    pub fn is_current(&self, qid: i32, query: Option<&str>, provider: &str) -> bool {
+        if qid < self.stale_qid.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let mut issued = self.issued.lock().unwrap();
+
+        let Some(issued) = issued.as_mut() else {
+            return false;
+        };
+
+        if query.is_some_and(|q| q != issued.query)
+            || !(issued.providers.is_empty()
+                || issued.providers.iter().any(|p| is_provider(p, provider)))
+        {
+            return false;
+        }
+
+        match (issued.qid, query) {
+            (0, Some(_)) => {
+                issued.qid = qid;
+                true
+            }
+            (0, None) => true,
+            // the same text sent twice before either was answered, the later one wins
+            (current, Some(_)) if qid > current => {
+                self.superseded_qid.store(current, Ordering::SeqCst);
+                issued.qid = qid;
+                true
+            }
+            (current, _) => qid == current,
+        }
+    }
+
+    // qid of answers that were shown but belong to an earlier query
+    pub fn take_superseded(&self) -> Option<i32> {
+        match self.superseded_qid.swap(0, Ordering::SeqCst) {
+            0 => None,
+            qid => Some(qid),
+        }
+    }
+
+    // a restarted elephant starts counting from scratch
+    pub fn reset_qids(&self) {
+        self.latest_qid.store(0, Ordering::SeqCst);
+        self.stale_qid.store(0, Ordering::SeqCst);
+        self.superseded_qid.store(0, Ordering::SeqCst);
+        *self.issued.lock().unwrap() = None;
+    }
+}
+
+// "menus:other" lives wherever "menus" does
+fn is_provider(name: &str, provider: &str) -> bool {
+    name == provider
+        || provider
+            .split_once(':')
+            .is_some_and(|(base, _)| name == base)
+}
+
+// index of the backend serving `provider`, falling back to the first one
+pub fn route(backends: &[Arc<Backend>], provider: &str) -> usize {
+    backends
+        .iter()
+        .skip(1)
+        .position(|b| b.serves(provider))
+        .map_or(0, |i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_to_announcing_backend() {
+        let backends = vec![
+            Arc::new(Backend::new(PathBuf::from("primary"), Vec::new())),
+            Arc::new(Backend::new(
+                PathBuf::from("work"),
+                vec!["todo".to_string(), "menus".to_string()],
+            )),
+        ];
+
+        assert_eq!(route(&backends, "desktopapplications"), 0);
+        assert_eq!(route(&backends, "todo"), 1);
+        assert_eq!(route(&backends, "menus:other"), 1);
+        assert_eq!(route(&backends, "todos"), 0);
+
+        backends[1].set_announced(vec!["files".to_string()]);
+        assert_eq!(route(&backends, "files"), 1);
+    }
+
+    #[test]
+    fn only_the_last_query_is_current() {
+        let backend = Backend::new(PathBuf::from("primary"), Vec::new());
+
+        let mut req = QueryRequest::new();
+        req.providers = vec!["desktopapplications".to_string(), "menus".to_string()];
+
+        req.query = "fire".to_string();
+        backend.issue(Some(&req));
+        backend.saw_qid(3);
+        assert!(backend.is_current(3, Some("fire"), "desktopapplications"));
+
+        // sent before either is answered
+        req.query = "firef".to_string();
+        backend.issue(Some(&req));
+        req.query = "firefo".to_string();
+        backend.issue(Some(&req));
+
+        assert!(!backend.is_current(3, Some("fire"), "desktopapplications"));
+        backend.saw_qid(4);
+        assert!(!backend.is_current(4, Some("firef"), "desktopapplications"));
+
+        backend.saw_qid(5);
+        assert!(backend.is_current(5, None, "desktopapplications"));
+        assert!(backend.is_current(5, Some("firefo"), "desktopapplications"));
+        assert!(!backend.is_current(5, Some("firefo"), "files"));
+        assert!(backend.is_current(5, Some("firefo"), "menus:other"));
+        assert_eq!(backend.take_superseded(), None);
+
+        // the same text again, its answers replace the first ones
+        backend.issue(Some(&req));
+        backend.issue(Some(&req));
+        backend.saw_qid(7);
+        assert!(backend.is_current(6, Some("firefo"), "desktopapplications"));
+        assert!(backend.is_current(7, Some("firefo"), "desktopapplications"));
+        assert!(!backend.is_current(6, Some("firefo"), "desktopapplications"));
+        assert_eq!(backend.take_superseded(), Some(6));
+        assert_eq!(backend.take_superseded(), None);
+
+        backend.issue(None);
+        assert!(!backend.is_current(8, Some("firefo"), "desktopapplications"));
+
+        backend.reset_qids();
+        assert!(!backend.is_current(1, Some("firefo"), "desktopapplications"));
+    }
+}
@@ -0,0 +1,459 @@
+use crate::protos::generated_proto::activate::ActivateRequest;
+use crate::protos::generated_proto::error::ErrorResponse;
+use crate::protos::generated_proto::hello::{HelloRequest, HelloResponse};
+use crate::protos::generated_proto::query::{QueryRequest, QueryResponse};
+use crate::protos::generated_proto::subscribe::{SubscribeRequest, SubscribeResponse};
+use protobuf::Message;
+use std::fmt;
+use std::io::{self, Read};
+
+// every frame is [type: u8][length: u32 big endian][payload]
+pub const HEADER_LEN: usize = 5;
+
+// anything above this is a desynced stream or a broken peer, not a real item
+pub const MAX_PAYLOAD_LEN: usize = 16 * 1024 * 1024;
+
+// walker -> elephant
+pub const FRAME_QUERY: u8 = 0;
+pub const FRAME_ACTIVATE: u8 = 1;
+pub const FRAME_SUBSCRIBE: u8 = 2;
+
+// both directions
+pub const FRAME_HELLO: u8 = 3;
+
+// elephant -> walker
+pub const FRAME_ITEM: u8 = 0;
+pub const FRAME_UPDATE: u8 = 1;
+pub const FRAME_ASYNC_DONE: u8 = 2;
+// a subscription event on a shared stream, `route` says which one
+pub const FRAME_EVENT: u8 = 4;
+// empty from elephants that don't report errors, an `ErrorResponse` otherwise
+pub const FRAME_IGNORED: u8 = 230;
+pub const FRAME_CLEAR: u8 = 254;
+pub const FRAME_DONE: u8 = 255;
+
+// subscription events reuse type 0, so decoding depends on the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Query,
+    Subscription,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Query(QueryRequest),
+    Activate(ActivateRequest),
+    Subscribe(SubscribeRequest),
+    Hello(HelloRequest),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Item(QueryResponse),
+    Update(QueryResponse),
+    AsyncDone,
+    Ignored,
+    Error(ErrorResponse),
+    Clear,
+    Done,
+    Subscription(SubscribeResponse),
+    Hello(HelloResponse),
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    Decode(protobuf::Error),
+    UnknownType(u8),
+    TooLarge(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "io: {e}"),
+            FrameError::Decode(e) => write!(f, "decode: {e}"),
+            FrameError::UnknownType(t) => write!(f, "unknown frame type: {t}"),
+            FrameError::TooLarge(len) => {
+                write!(f, "frame too large: {len} bytes (max {MAX_PAYLOAD_LEN})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+impl From<protobuf::Error> for FrameError {
+    fn from(e: protobuf::Error) -> Self {
+        FrameError::Decode(e)
+    }
+}
+
+impl FrameError {
+    // unknown and undecodable frames are skipped in full, the stream stays usable
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, FrameError::UnknownType(_) | FrameError::Decode(_))
+    }
+}
+
+impl Request {
+    pub fn frame_type(&self) -> u8 {
+        match self {
+            Request::Query(_) => FRAME_QUERY,
+            Request::Activate(_) => FRAME_ACTIVATE,
+            Request::Subscribe(_) => FRAME_SUBSCRIBE,
+            Request::Hello(_) => FRAME_HELLO,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match self {
+            Request::Query(req) => req.write_to_bytes(),
+            Request::Activate(req) => req.write_to_bytes(),
+            Request::Subscribe(req) => req.write_to_bytes(),
+            Request::Hello(req) => req.write_to_bytes(),
+        }
+        .expect("couldn't serialize request");
+
+        encode_frame(self.frame_type(), &payload)
+    }
+
+    #[cfg(test)]
+    pub fn parse(frame_type: u8, payload: &[u8]) -> Result<Self, FrameError> {
+        match frame_type {
+            FRAME_QUERY => Ok(Request::Query(QueryRequest::parse_from_bytes(payload)?)),
+            FRAME_ACTIVATE => Ok(Request::Activate(ActivateRequest::parse_from_bytes(
+                payload,
+            )?)),
+            FRAME_SUBSCRIBE => Ok(Request::Subscribe(SubscribeRequest::parse_from_bytes(
+                payload,
+            )?)),
+            FRAME_HELLO => Ok(Request::Hello(HelloRequest::parse_from_bytes(payload)?)),
+            t => Err(FrameError::UnknownType(t)),
+        }
+    }
+}
+
+impl Response {
+    #[cfg(test)]
+    pub fn frame_type(&self) -> u8 {
+        match self {
+            Response::Item(_) | Response::Subscription(_) => FRAME_ITEM,
+            Response::Update(_) => FRAME_UPDATE,
+            Response::AsyncDone => FRAME_ASYNC_DONE,
+            Response::Ignored | Response::Error(_) => FRAME_IGNORED,
+            Response::Clear => FRAME_CLEAR,
+            Response::Done => FRAME_DONE,
+            Response::Hello(_) => FRAME_HELLO,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn encode(&self) -> Vec<u8> {
+        let payload = match self {
+            Response::Item(resp) | Response::Update(resp) => {
+                resp.write_to_bytes().expect("couldn't serialize response")
+            }
+            Response::Subscription(resp) => {
+                resp.write_to_bytes().expect("couldn't serialize response")
+            }
+            Response::Hello(resp) => resp.write_to_bytes().expect("couldn't serialize response"),
+            Response::Error(resp) => resp.write_to_bytes().expect("couldn't serialize response"),
+            _ => Vec::new(),
+        };
+
+        encode_frame(self.frame_type(), &payload)
+    }
+
+    pub fn parse(frame_type: u8, payload: &[u8], channel: Channel) -> Result<Self, FrameError> {
+        match (channel, frame_type) {
+            (Channel::Subscription, FRAME_ITEM) => Ok(Response::Subscription(
+                SubscribeResponse::parse_from_bytes(payload)?,
+            )),
+            (Channel::Subscription, t) => Err(FrameError::UnknownType(t)),
+            (Channel::Query, FRAME_ITEM) => {
+                Ok(Response::Item(QueryResponse::parse_from_bytes(payload)?))
+            }
+            (Channel::Query, FRAME_UPDATE) => {
+                Ok(Response::Update(QueryResponse::parse_from_bytes(payload)?))
+            }
+            (Channel::Query, FRAME_ASYNC_DONE) => Ok(Response::AsyncDone),
+            (Channel::Query, FRAME_IGNORED) if payload.is_empty() => Ok(Response::Ignored),
+            (Channel::Query, FRAME_IGNORED) => {
+                Ok(Response::Error(ErrorResponse::parse_from_bytes(payload)?))
+            }
+            (Channel::Query, FRAME_CLEAR) => Ok(Response::Clear),
+            (Channel::Query, FRAME_DONE) => Ok(Response::Done),
+            (Channel::Query, FRAME_HELLO) => {
+                Ok(Response::Hello(HelloResponse::parse_from_bytes(payload)?))
+            }
+            (Channel::Query, FRAME_EVENT) => Ok(Response::Subscription(
+                SubscribeResponse::parse_from_bytes(payload)?,
+            )),
+            (Channel::Query, t) => Err(FrameError::UnknownType(t)),
+        }
+    }
+}
+
+pub fn encode_frame(frame_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(HEADER_LEN + payload.len());
+    buffer.push(frame_type);
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(payload);
+    buffer
+}
+
+pub fn decode_header(header: &[u8; HEADER_LEN]) -> Result<(u8, usize), FrameError> {
+    let length = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+
+    if length > MAX_PAYLOAD_LEN {
+        return Err(FrameError::TooLarge(length));
+    }
+
+    Ok((header[0], length))
+}
+
+// None until `buf` holds a whole frame, otherwise the frame and its length
+#[cfg(test)]
+pub fn decode(buf: &[u8], channel: Channel) -> Result<Option<(Response, usize)>, FrameError> {
+    let Some(header) = buf.first_chunk::<HEADER_LEN>() else {
+        return Ok(None);
+    };
+
+    let (frame_type, length) = decode_header(header)?;
+
+    let Some(payload) = buf.get(HEADER_LEN..HEADER_LEN + length) else {
+        return Ok(None);
+    };
+
+    Response::parse(frame_type, payload, channel).map(|resp| Some((resp, HEADER_LEN + length)))
+}
+
+pub fn read_raw<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>), FrameError> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+
+    let (frame_type, length) = decode_header(&header)?;
+
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+
+    Ok((frame_type, payload))
+}
+
+// the whole payload is read before decoding, so an unknown type keeps the stream in sync
+pub fn read_frame<R: Read>(reader: &mut R, channel: Channel) -> Result<Response, FrameError> {
+    let (frame_type, payload) = read_raw(reader)?;
+    Response::parse(frame_type, &payload, channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::generated_proto::query::query_response::Item;
+    use std::io::Cursor;
+
+    // hands out at most one byte per read, like a slow socket
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn item_response(text: &str, qid: i32) -> QueryResponse {
+        let mut item = Item::new();
+        item.text = text.to_string();
+        item.provider = "desktopapplications".to_string();
+
+        let mut resp = QueryResponse::new();
+        resp.item = protobuf::MessageField::some(item);
+        resp.qid = qid;
+        resp
+    }
+
+    fn error_response(provider: &str, message: &str) -> ErrorResponse {
+        let mut resp = ErrorResponse::new();
+        resp.provider = provider.to_string();
+        resp.message = message.to_string();
+        resp
+    }
+
+    #[test]
+    fn request_roundtrip() {
+        let mut req = QueryRequest::new();
+        req.query = "firefox".to_string();
+        req.providers = vec!["desktopapplications".to_string()];
+        req.maxresults = 50;
+
+        let buffer = Request::Query(req.clone()).encode();
+
+        assert_eq!(buffer[0], FRAME_QUERY);
+        assert_eq!(
+            u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize,
+            buffer.len() - HEADER_LEN
+        );
+
+        let (frame_type, payload) = read_raw(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(
+            Request::parse(frame_type, &payload).unwrap(),
+            Request::Query(req)
+        );
+    }
+
+    #[test]
+    fn response_roundtrip() {
+        let frames = vec![
+            Response::Item(item_response("firefox", 1)),
+            Response::Update(item_response("firefox", 1)),
+            Response::Clear,
+            Response::AsyncDone,
+            Response::Ignored,
+            Response::Error(error_response("websearch", "no engines configured")),
+            Response::Done,
+        ];
+
+        let buffer: Vec<u8> = frames.iter().flat_map(Response::encode).collect();
+        let mut reader = Cursor::new(buffer);
+
+        for frame in frames {
+            assert_eq!(read_frame(&mut reader, Channel::Query).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn empty_error_is_ignored() {
+        let buffer = encode_frame(FRAME_IGNORED, &[]);
+
+        assert_eq!(
+            read_frame(&mut Cursor::new(buffer), Channel::Query).unwrap(),
+            Response::Ignored
+        );
+    }
+
+    #[test]
+    fn subscription_channel() {
+        let mut resp = SubscribeResponse::new();
+        resp.value = "bluetooth:connect".to_string();
+
+        let buffer = Response::Subscription(resp.clone()).encode();
+
+        assert_eq!(
+            read_frame(&mut Cursor::new(&buffer), Channel::Subscription).unwrap(),
+            Response::Subscription(resp.clone())
+        );
+        assert!(matches!(
+            read_frame(
+                &mut Cursor::new(Response::Done.encode()),
+                Channel::Subscription
+            ),
+            Err(FrameError::UnknownType(FRAME_DONE))
+        ));
+
+        resp.route = 3;
+        let buffer = encode_frame(FRAME_EVENT, &resp.write_to_bytes().unwrap());
+
+        assert_eq!(
+            read_frame(&mut Cursor::new(&buffer), Channel::Query).unwrap(),
+            Response::Subscription(resp)
+        );
+    }
+
+    #[test]
+    fn partial_reads() {
+        let frame = Response::Item(item_response("partial", 7));
+        let buffer = frame.encode();
+
+        for i in 0..buffer.len() {
+            assert!(decode(&buffer[..i], Channel::Query).unwrap().is_none());
+        }
+
+        let (decoded, used) = decode(&buffer, Channel::Query).unwrap().unwrap();
+        assert_eq!(decoded, frame);
+        assert_eq!(used, buffer.len());
+
+        assert_eq!(
+            read_frame(&mut Trickle(&buffer), Channel::Query).unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let buffer = Response::Item(item_response("cut off", 1)).encode();
+
+        match read_frame(
+            &mut Cursor::new(&buffer[..buffer.len() - 1]),
+            Channel::Query,
+        ) {
+            Err(FrameError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("expected eof, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_type_is_skipped() {
+        let mut buffer = encode_frame(42, b"from the future");
+        buffer.extend(Response::Done.encode());
+
+        let mut reader = Cursor::new(buffer);
+
+        let err = read_frame(&mut reader, Channel::Query).unwrap_err();
+        assert!(matches!(err, FrameError::UnknownType(42)));
+        assert!(err.is_recoverable());
+
+        assert_eq!(
+            read_frame(&mut reader, Channel::Query).unwrap(),
+            Response::Done
+        );
+    }
+
+    #[test]
+    fn oversized_length() {
+        let mut buffer = vec![FRAME_ITEM];
+        buffer.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        assert!(matches!(
+            decode(&buffer, Channel::Query),
+            Err(FrameError::TooLarge(len)) if len == u32::MAX as usize
+        ));
+        assert!(matches!(
+            read_frame(&mut Cursor::new(buffer), Channel::Query),
+            Err(FrameError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn garbage_payload() {
+        let mut buffer = encode_frame(FRAME_ITEM, &[0xff, 0xff, 0xff]);
+        buffer.extend(Response::Done.encode());
+
+        let mut reader = Cursor::new(buffer);
+
+        let err = read_frame(&mut reader, Channel::Query).unwrap_err();
+        assert!(matches!(err, FrameError::Decode(_)));
+        assert!(err.is_recoverable());
+
+        assert_eq!(
+            read_frame(&mut reader, Channel::Query).unwrap(),
+            Response::Done
+        );
+    }
+}
@@ -0,0 +1,301 @@
+// the version handshake every stream starts with
+
+use super::frame::{
+    self, Channel, FRAME_ACTIVATE, FRAME_ASYNC_DONE, FRAME_CLEAR, FRAME_DONE, FRAME_EVENT,
+    FRAME_HELLO, FRAME_IGNORED, FRAME_ITEM, FRAME_QUERY, FRAME_SUBSCRIBE, FRAME_UPDATE, FrameError,
+    Request, Response,
+};
+use super::reconnect::{self, Identity};
+use crate::protos::generated_proto::hello::{HelloRequest, HelloResponse, hello_response};
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// oldest elephant protocol walker still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// elephant predating the handshake never answers, this is all it costs once per instance
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(250);
+
+// instances known to predate the handshake, connected to without one
+static LEGACY: Mutex<Vec<(PathBuf, Identity)>> = Mutex::new(Vec::new());
+
+// requests elephant accepted before the handshake existed
+const LEGACY_FRAMES: [u8; 3] = [FRAME_QUERY, FRAME_ACTIVATE, FRAME_SUBSCRIBE];
+
+// responses walker understands
+const RESPONSE_FRAMES: [u8; 8] = [
+    FRAME_ITEM,
+    FRAME_UPDATE,
+    FRAME_ASYNC_DONE,
+    FRAME_HELLO,
+    FRAME_EVENT,
+    FRAME_IGNORED,
+    FRAME_CLEAR,
+    FRAME_DONE,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hello {
+    // 0 for an elephant without the handshake
+    pub version: u32,
+    // request frame types elephant accepts
+    pub frames: Vec<u8>,
+    pub providers: Vec<hello_response::Provider>,
+    // subscriptions can share the query stream
+    pub routed_subscriptions: bool,
+}
+
+impl Hello {
+    pub fn legacy() -> Self {
+        Self {
+            version: 0,
+            frames: LEGACY_FRAMES.to_vec(),
+            providers: Vec::new(),
+            routed_subscriptions: false,
+        }
+    }
+
+    pub fn supports(&self, frame_type: u8) -> bool {
+        self.frames.contains(&frame_type)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Incompatible {
+    pub version: u32,
+    pub min_version: u32,
+}
+
+impl fmt::Display for Incompatible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let update = if self.version < MIN_PROTOCOL_VERSION {
+            "elephant"
+        } else {
+            "walker"
+        };
+
+        write!(
+            f,
+            "elephant speaks protocol {} (needs at least {}), walker speaks {} (needs at least {}). Please update {update}.",
+            self.version, self.min_version, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectError {
+    Io(io::Error),
+    Frame(FrameError),
+    Incompatible(Incompatible),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Io(e) => write!(f, "{e}"),
+            ConnectError::Frame(e) => write!(f, "{e}"),
+            ConnectError::Incompatible(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<io::Error> for ConnectError {
+    fn from(e: io::Error) -> Self {
+        ConnectError::Io(e)
+    }
+}
+
+impl From<FrameError> for ConnectError {
+    fn from(e: FrameError) -> Self {
+        ConnectError::Frame(e)
+    }
+}
+
+pub fn request() -> HelloRequest {
+    let mut req = HelloRequest::new();
+    req.version = PROTOCOL_VERSION;
+    req.min_version = MIN_PROTOCOL_VERSION;
+    req.frames = RESPONSE_FRAMES.into_iter().map(u32::from).collect();
+    req
+}
+
+pub fn check(resp: HelloResponse) -> Result<Hello, Incompatible> {
+    if resp.version < MIN_PROTOCOL_VERSION || resp.min_version > PROTOCOL_VERSION {
+        return Err(Incompatible {
+            version: resp.version,
+            min_version: resp.min_version,
+        });
+    }
+
+    Ok(Hello {
+        version: resp.version,
+        frames: resp
+            .frames
+            .iter()
+            .filter_map(|t| u8::try_from(*t).ok())
+            .collect(),
+        providers: resp.providers,
+        routed_subscriptions: resp.routed_subscriptions,
+    })
+}
+
+// connects and introduces walker. Legacy instances are remembered, so
+// reconnecting to them neither waits for an answer nor risks a hang-up.
+pub fn connect(path: &Path) -> Result<(UnixStream, Hello), ConnectError> {
+    let identity = reconnect::identity(path);
+    let known = |legacy: &[(PathBuf, Identity)]| {
+        identity.is_some_and(|id| legacy.iter().any(|(p, i)| p == path && *i == id))
+    };
+
+    let mut stream = UnixStream::connect(path)?;
+
+    if known(&LEGACY.lock().unwrap()) {
+        return Ok((stream, Hello::legacy()));
+    }
+
+    let (stream, hello) = match handshake(&mut stream)? {
+        Some(hello) => (stream, hello),
+        // the hello frame is still unread or it was hung up on, try again without
+        None => (UnixStream::connect(path)?, Hello::legacy()),
+    };
+
+    if hello.version == 0
+        && let Some(identity) = identity
+    {
+        let mut legacy = LEGACY.lock().unwrap();
+        legacy.retain(|(p, _)| p != path);
+        legacy.push((path.to_path_buf(), identity));
+    }
+
+    Ok((stream, hello))
+}
+
+// None if elephant hung up or didn't answer, the stream is of no use then
+pub fn handshake(stream: &mut UnixStream) -> Result<Option<Hello>, ConnectError> {
+    if let Err(e) = stream.write_all(&Request::Hello(request()).encode()) {
+        return if hung_up(&e) { Ok(None) } else { Err(e.into()) };
+    }
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let result = frame::read_frame(stream, Channel::Query);
+    let _ = stream.set_read_timeout(None);
+
+    match result {
+        Ok(Response::Hello(resp)) => check(resp).map(Some).map_err(ConnectError::Incompatible),
+        // e.g. an ignored frame from an elephant that doesn't know hello
+        Ok(_) => Ok(Some(Hello::legacy())),
+        Err(FrameError::Io(e))
+            if hung_up(&e)
+                || matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+        {
+            Ok(None)
+        }
+        Err(e) if e.is_recoverable() => Ok(Some(Hello::legacy())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn hung_up(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elephant::mock::{Fixtures, MockElephant, hello_response};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn hello(version: u32, min_version: u32) -> HelloResponse {
+        let mut resp = hello_response();
+        resp.version = version;
+        resp.min_version = min_version;
+        resp
+    }
+
+    #[test]
+    fn compatibility() {
+        assert!(check(hello_response()).is_ok());
+        assert!(check(hello(PROTOCOL_VERSION + 1, MIN_PROTOCOL_VERSION)).is_ok());
+
+        assert_eq!(
+            check(hello(PROTOCOL_VERSION + 2, PROTOCOL_VERSION + 1)),
+            Err(Incompatible {
+                version: PROTOCOL_VERSION + 2,
+                min_version: PROTOCOL_VERSION + 1
+            })
+        );
+        assert!(check(hello(MIN_PROTOCOL_VERSION - 1, 0)).is_err());
+    }
+
+    #[test]
+    fn handshake_with_mock() {
+        let incompatible = MockElephant::start(Fixtures {
+            hello: Some(hello(PROTOCOL_VERSION + 5, PROTOCOL_VERSION + 5)),
+            ..Default::default()
+        });
+        let mut stream = UnixStream::connect(incompatible.socket_path()).unwrap();
+
+        assert!(matches!(
+            handshake(&mut stream),
+            Err(ConnectError::Incompatible(_))
+        ));
+
+        let current = MockElephant::start(Fixtures::default());
+        let mut stream = UnixStream::connect(current.socket_path()).unwrap();
+
+        let hello = handshake(&mut stream).unwrap().unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert!(hello.supports(FRAME_HELLO));
+    }
+
+    #[test]
+    fn legacy_elephant() {
+        let legacy = MockElephant::start(Fixtures {
+            legacy: true,
+            ..Default::default()
+        });
+        let mut stream = UnixStream::connect(legacy.socket_path()).unwrap();
+
+        assert_eq!(handshake(&mut stream).unwrap(), None);
+        assert!(legacy.wait_for(TIMEOUT, |r| r.len() == 1));
+
+        // the unanswered hello stays behind on the first stream
+        let (_, hello) = connect(legacy.socket_path()).unwrap();
+        assert_eq!(hello, Hello::legacy());
+        assert!(legacy.wait_for(TIMEOUT, |_| legacy.accepted() == 3));
+    }
+
+    #[test]
+    fn legacy_elephant_hanging_up() {
+        let legacy = MockElephant::start(Fixtures {
+            legacy: true,
+            hang_up_on_hello: true,
+            ..Default::default()
+        });
+
+        let (_, hello) = connect(legacy.socket_path()).unwrap();
+        assert_eq!(hello, Hello::legacy());
+
+        // known by now, no second hello
+        let (_, hello) = connect(legacy.socket_path()).unwrap();
+        assert_eq!(hello, Hello::legacy());
+
+        assert!(legacy.wait_for(TIMEOUT, |_| legacy.accepted() == 3));
+        assert_eq!(legacy.requests().len(), 1);
+    }
+}
@@ -0,0 +1,287 @@
+// scripted stand-in for elephant, so the socket code is tested without one
+
+use super::frame::{
+    self, FRAME_ACTIVATE, FRAME_EVENT, FRAME_HELLO, FRAME_QUERY, FRAME_SUBSCRIBE, Request, Response,
+};
+use super::hello::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use crate::protos::generated_proto::hello::HelloResponse;
+use crate::protos::generated_proto::query::QueryResponse;
+use crate::protos::generated_proto::subscribe::SubscribeResponse;
+use protobuf::Message;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// queries are matched by their exact text, unknown ones get a clear + done.
+// Subscribers get their values right away. A legacy elephant doesn't answer
+// the hello, or even hangs up on it.
+#[derive(Debug, Clone, Default)]
+pub struct Fixtures {
+    pub queries: HashMap<String, Vec<QueryResponse>>,
+    pub subscriptions: HashMap<String, Vec<String>>,
+    pub hello: Option<HelloResponse>,
+    pub legacy: bool,
+    pub hang_up_on_hello: bool,
+}
+
+type Held = Vec<(UnixStream, Vec<Response>)>;
+
+#[derive(Debug, Default)]
+struct Shared {
+    fixtures: Fixtures,
+    requests: Mutex<Vec<Request>>,
+    connections: Mutex<Vec<UnixStream>>,
+    subscribers: Mutex<Vec<(String, u32, UnixStream)>>,
+    // answers to queries, kept back while holding
+    held: Mutex<Option<Held>>,
+    accepted: AtomicUsize,
+    qid: AtomicI32,
+    stopped: AtomicBool,
+}
+
+#[derive(Debug)]
+pub struct MockElephant {
+    dir: PathBuf,
+    path: PathBuf,
+    shared: Arc<Shared>,
+}
+
+impl MockElephant {
+    pub fn start(fixtures: Fixtures) -> Self {
+        let mut dir = env::temp_dir();
+        dir.push(format!(
+            "walker-mock-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("elephant.sock");
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let shared = Arc::new(Shared {
+            fixtures,
+            ..Default::default()
+        });
+
+        let accept_shared = shared.clone();
+        thread::spawn(move || accept_loop(listener, accept_shared));
+
+        Self { dir, path, shared }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    pub fn accepted(&self) -> usize {
+        self.shared.accepted.load(Ordering::SeqCst)
+    }
+
+    // polls the recorded requests until `f` is satisfied
+    pub fn wait_for<F>(&self, timeout: Duration, f: F) -> bool
+    where
+        F: Fn(&[Request]) -> bool,
+    {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            if f(&self.shared.requests.lock().unwrap()) {
+                return true;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        false
+    }
+
+    pub fn push(&self, provider: &str, value: &str) {
+        push(&self.shared, provider, value);
+    }
+
+    // keeps the answers to queries until `release`
+    pub fn hold(&self) {
+        *self.shared.held.lock().unwrap() = Some(Vec::new());
+    }
+
+    // sends the held answers in the order the queries came in
+    pub fn release(&self) {
+        for (mut conn, frames) in self.shared.held.lock().unwrap().take().unwrap_or_default() {
+            for frame in frames {
+                let _ = conn.write_all(&frame.encode());
+            }
+        }
+    }
+
+    // like an elephant restart
+    pub fn disconnect_all(&self) {
+        for conn in self.shared.connections.lock().unwrap().drain(..) {
+            let _ = conn.shutdown(Shutdown::Both);
+        }
+
+        self.shared.subscribers.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockElephant {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        self.disconnect_all();
+
+        // wake up the blocking accept
+        let _ = UnixStream::connect(&self.path);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn accept_loop(listener: UnixListener, shared: Arc<Shared>) {
+    for conn in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let Ok(conn) = conn else {
+            continue;
+        };
+
+        shared.accepted.fetch_add(1, Ordering::SeqCst);
+
+        if let Ok(clone) = conn.try_clone() {
+            shared.connections.lock().unwrap().push(clone);
+        }
+
+        let shared = shared.clone();
+        thread::spawn(move || handle_connection(conn, shared));
+    }
+}
+
+fn handle_connection(mut conn: UnixStream, shared: Arc<Shared>) {
+    let Ok(mut reader) = conn.try_clone() else {
+        return;
+    };
+
+    while let Ok((frame_type, payload)) = frame::read_raw(&mut reader) {
+        let Ok(req) = Request::parse(frame_type, &payload) else {
+            continue;
+        };
+
+        if matches!(req, Request::Hello(_)) && shared.fixtures.hang_up_on_hello {
+            shared.requests.lock().unwrap().push(req);
+            let _ = conn.shutdown(Shutdown::Both);
+            return;
+        }
+
+        let record = req.clone();
+        let is_query = matches!(req, Request::Query(_));
+
+        let frames = match req {
+            Request::Query(req) => {
+                let qid = shared.qid.fetch_add(1, Ordering::SeqCst) + 1;
+
+                match shared.fixtures.queries.get(&req.query) {
+                    Some(responses) => responses
+                        .iter()
+                        .cloned()
+                        .map(|mut resp| {
+                            resp.qid = qid;
+                            resp.query = req.query.clone();
+                            Response::Item(resp)
+                        })
+                        .chain([Response::Done])
+                        .collect(),
+                    None => vec![Response::Clear, Response::Done],
+                }
+            }
+            Request::Activate(_) => vec![Response::AsyncDone],
+            Request::Hello(_) if shared.fixtures.legacy => Vec::new(),
+            Request::Hello(_) => vec![Response::Hello(
+                shared.fixtures.hello.clone().unwrap_or_else(hello_response),
+            )],
+            Request::Subscribe(req) => {
+                if let Ok(clone) = conn.try_clone() {
+                    shared.subscribers.lock().unwrap().push((
+                        req.provider.clone(),
+                        req.route,
+                        clone,
+                    ));
+                }
+
+                if let Some(values) = shared.fixtures.subscriptions.get(&req.provider) {
+                    values
+                        .iter()
+                        .for_each(|value| push(&shared, &req.provider, value));
+                }
+
+                Vec::new()
+            }
+        };
+
+        if is_query
+            && let Some(held) = shared.held.lock().unwrap().as_mut()
+            && let Ok(clone) = conn.try_clone()
+        {
+            held.push((clone, frames));
+            shared.requests.lock().unwrap().push(record);
+            continue;
+        }
+
+        // recorded once handled, so a test seeing a subscribe can push to it
+        shared.requests.lock().unwrap().push(record);
+
+        for frame in frames {
+            if conn.write_all(&frame.encode()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// an elephant speaking walker's protocol version
+pub fn hello_response() -> HelloResponse {
+    let mut resp = HelloResponse::new();
+    resp.version = PROTOCOL_VERSION;
+    resp.min_version = MIN_PROTOCOL_VERSION;
+    resp.frames = [FRAME_QUERY, FRAME_ACTIVATE, FRAME_SUBSCRIBE, FRAME_HELLO]
+        .into_iter()
+        .map(u32::from)
+        .collect();
+    resp.routed_subscriptions = true;
+    resp
+}
+
+fn push(shared: &Shared, provider: &str, value: &str) {
+    shared
+        .subscribers
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .filter(|(p, _, _)| p == provider)
+        .for_each(|(_, route, conn)| {
+            let mut resp = SubscribeResponse::new();
+            resp.value = value.to_string();
+            resp.route = *route;
+
+            let buffer = if *route == 0 {
+                Response::Subscription(resp).encode()
+            } else {
+                frame::encode_frame(FRAME_EVENT, &resp.write_to_bytes().unwrap())
+            };
+
+            let _ = conn.write_all(&buffer);
+        });
+}
@@ -0,0 +1,257 @@
+pub mod backend;
+pub mod frame;
+pub mod hello;
+#[cfg(test)]
+pub mod mock;
+pub mod reconnect;
+pub mod supervisor;
+
+use crate::protos::generated_proto::error::ErrorResponse;
+use crate::protos::generated_proto::subscribe::SubscribeRequest;
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+pub use frame::{Channel, FrameError, Request, Response};
+pub use hello::{ConnectError, Hello};
+
+pub type Handler = Box<dyn Fn(Response) + Send + Sync>;
+pub type DisconnectHandler = Box<dyn Fn(FrameError) + Send + Sync>;
+
+// queries and activations use QUERY_ROUTE, every subscription gets its own
+pub type RouteId = u32;
+pub const QUERY_ROUTE: RouteId = 0;
+
+struct Dispatcher {
+    handlers: RwLock<HashMap<RouteId, Handler>>,
+    on_disconnect: DisconnectHandler,
+    closed: AtomicBool,
+}
+
+impl Dispatcher {
+    fn dispatch(&self, route: RouteId, frame: Response) {
+        if let Some(handler) = self.handlers.read().unwrap().get(&route) {
+            handler(frame);
+        }
+    }
+
+    // only the first failing stream reports, the client is torn down as a whole
+    fn disconnect(&self, e: FrameError) {
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            (self.on_disconnect)(e);
+        }
+    }
+}
+
+// subscriptions share the stream if elephant routes them, older ones need a stream each
+pub struct ElephantClient {
+    path: PathBuf,
+    stream: UnixStream,
+    hello: Hello,
+    subscriptions: Vec<UnixStream>,
+    dispatcher: Arc<Dispatcher>,
+    next_route: RouteId,
+}
+
+impl ElephantClient {
+    pub fn connect<R, D>(
+        path: &Path,
+        on_response: R,
+        on_disconnect: D,
+    ) -> Result<Self, ConnectError>
+    where
+        R: Fn(Response) + Send + Sync + 'static,
+        D: Fn(FrameError) + Send + Sync + 'static,
+    {
+        let (stream, hello) = hello::connect(path)?;
+
+        let mut handlers: HashMap<RouteId, Handler> = HashMap::new();
+        handlers.insert(QUERY_ROUTE, Box::new(on_response));
+
+        let dispatcher = Arc::new(Dispatcher {
+            handlers: RwLock::new(handlers),
+            on_disconnect: Box::new(on_disconnect),
+            closed: AtomicBool::new(false),
+        });
+
+        listen(&stream, Channel::Query, QUERY_ROUTE, dispatcher.clone())?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            stream,
+            hello,
+            subscriptions: Vec::new(),
+            dispatcher,
+            next_route: QUERY_ROUTE + 1,
+        })
+    }
+
+    pub fn hello(&self) -> &Hello {
+        &self.hello
+    }
+
+    pub fn send(&mut self, req: &Request) -> io::Result<()> {
+        self.stream.write_all(&req.encode())
+    }
+
+    pub fn subscribe<H>(
+        &mut self,
+        req: SubscribeRequest,
+        handler: H,
+    ) -> Result<RouteId, ConnectError>
+    where
+        H: Fn(Response) + Send + Sync + 'static,
+    {
+        let route = self.next_route;
+        self.next_route += 1;
+
+        if self.hello.routed_subscriptions {
+            let mut req = req;
+            req.route = route;
+
+            self.add_handler(route, handler);
+            self.send(&Request::Subscribe(req))?;
+
+            return Ok(route);
+        }
+
+        let (mut stream, _) = hello::connect(&self.path)?;
+
+        self.add_handler(route, handler);
+
+        stream.write_all(&Request::Subscribe(req).encode())?;
+        listen(
+            &stream,
+            Channel::Subscription,
+            route,
+            self.dispatcher.clone(),
+        )?;
+
+        self.subscriptions.push(stream);
+
+        Ok(route)
+    }
+
+    fn add_handler<H>(&self, route: RouteId, handler: H)
+    where
+        H: Fn(Response) + Send + Sync + 'static,
+    {
+        self.dispatcher
+            .handlers
+            .write()
+            .unwrap()
+            .insert(route, Box::new(handler));
+    }
+}
+
+impl Drop for ElephantClient {
+    fn drop(&mut self) {
+        // the reader threads will error out, nobody needs to hear about it
+        self.dispatcher.closed.store(true, Ordering::SeqCst);
+
+        let _ = self.stream.shutdown(Shutdown::Both);
+
+        for stream in &self.subscriptions {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn listen(
+    stream: &UnixStream,
+    channel: Channel,
+    route: RouteId,
+    dispatcher: Arc<Dispatcher>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    thread::spawn(move || {
+        loop {
+            match frame::read_frame(&mut reader, channel) {
+                Ok(Response::Subscription(resp)) if channel == Channel::Query => {
+                    dispatcher.dispatch(resp.route, Response::Subscription(resp))
+                }
+                Ok(frame) => dispatcher.dispatch(route, frame),
+                Err(e @ FrameError::Decode(_)) if channel == Channel::Query => {
+                    eprintln!("elephant: {e}");
+
+                    // whatever got lost was meant for the list, say so there too
+                    let mut resp = ErrorResponse::new();
+                    resp.message = e.to_string();
+                    dispatcher.dispatch(route, Response::Error(resp));
+                }
+                Err(e) if e.is_recoverable() => eprintln!("elephant: {e}"),
+                Err(e) => {
+                    dispatcher.disconnect(e);
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{Fixtures, MockElephant};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn pushed_values_reach_the_subscription() {
+        let mock = MockElephant::start(Fixtures::default());
+        subscribe_and_push(&mock);
+
+        // routed, so everything went over the one stream
+        assert_eq!(mock.accepted(), 1);
+    }
+
+    #[test]
+    fn legacy_subscriptions_get_their_own_stream() {
+        let mock = MockElephant::start(Fixtures {
+            legacy: true,
+            ..Default::default()
+        });
+        subscribe_and_push(&mock);
+
+        // the stream left with the unanswered hello, the query one and the subscription's
+        assert_eq!(mock.accepted(), 3);
+    }
+
+    fn subscribe_and_push(mock: &MockElephant) {
+        let mut client = ElephantClient::connect(mock.socket_path(), |_| {}, |_| {}).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+
+        let mut req = SubscribeRequest::new();
+        req.provider = "bluetooth".to_string();
+
+        client
+            .subscribe(req, move |frame| {
+                if let Response::Subscription(resp) = frame {
+                    let _ = tx.send(resp.value);
+                }
+            })
+            .unwrap();
+
+        assert!(mock.wait_for(TIMEOUT, |r| {
+            r.iter().any(|r| matches!(r, Request::Subscribe(_)))
+        }));
+
+        mock.push("bluetooth", "bluetooth:pair");
+        mock.push("menus", "menus:other");
+        mock.push("bluetooth", "bluetooth:connect");
+
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "bluetooth:pair");
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), "bluetooth:connect");
+    }
+}
@@ -0,0 +1,165 @@
+use notify::{RecursiveMode, Watcher};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, thread};
+
+// events are only a wake up call, the path is checked again either way. This
+// covers missed events and filesystems notify can't watch.
+const WATCH_FALLBACK: Duration = Duration::from_secs(5);
+
+// delays are picked from the upper half of each step, so walkers restarting
+// together don't hit elephant in lockstep
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self
+            .base
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(self.max);
+
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        half + half.mul_f64(jitter())
+    }
+}
+
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+
+    f64::from(nanos) / 1_000_000_000.0
+}
+
+// the closest existing parent is watched, elephant might not have created its
+// runtime dir yet
+pub fn wait_for_socket(path: &Path) {
+    wait_until(path, || path.exists());
+}
+
+// until elephant was restarted and replaced the socket
+pub fn wait_for_restart(path: &Path) {
+    let current = identity(path);
+    wait_until(path, || path.exists() && identity(path) != current);
+}
+
+pub type Identity = (u64, i64, i64);
+
+// inodes get reused quickly on tmpfs, the change time tells them apart
+pub fn identity(path: &Path) -> Option<Identity> {
+    fs::metadata(path)
+        .map(|m| (m.ino(), m.ctime(), m.ctime_nsec()))
+        .ok()
+}
+
+fn wait_until<F>(path: &Path, done: F)
+where
+    F: Fn() -> bool,
+{
+    while !done() {
+        let Some(dir) = watched_dir(path) else {
+            thread::sleep(WATCH_FALLBACK);
+            continue;
+        };
+
+        let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+
+        let watcher = notify::recommended_watcher(tx).and_then(|mut watcher| {
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+
+        let Ok(_watcher) = watcher else {
+            thread::sleep(WATCH_FALLBACK);
+            continue;
+        };
+
+        // once a missing parent shows up, the watch has to move one level down
+        while !done() && watched_dir(path).as_ref() == Some(&dir) {
+            if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(WATCH_FALLBACK) {
+                break;
+            }
+        }
+    }
+}
+
+fn watched_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|p| p.is_dir())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::{env, process};
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        let delays: Vec<Duration> = (0..10).map(|_| backoff.next_delay()).collect();
+
+        assert!(delays[0] >= Duration::from_millis(50) && delays[0] <= Duration::from_millis(100));
+        assert!(delays[3] >= Duration::from_millis(400));
+        assert!(delays.iter().all(|d| *d <= Duration::from_secs(1)));
+        assert!(delays[9] >= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn waits_for_socket_and_restart() {
+        let mut root = env::temp_dir();
+        root.push(format!("walker-wait-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        let path = root.join("elephant").join("elephant.sock");
+
+        let create = path.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            fs::create_dir_all(create.parent().unwrap()).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            UnixListener::bind(&create).unwrap()
+        });
+
+        wait_for_socket(&path);
+        assert!(path.exists());
+
+        let listener = handle.join().unwrap();
+
+        let restart = path.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(listener);
+            fs::remove_file(&restart).unwrap();
+            UnixListener::bind(&restart).unwrap()
+        });
+
+        wait_for_restart(&path);
+        assert!(path.exists());
+
+        drop(handle.join().unwrap());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
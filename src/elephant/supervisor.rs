@@ -0,0 +1,117 @@
+// elephant as a child of the service, so the two come and go together
+
+use super::reconnect::Backoff;
+use gtk4::{
+    Application,
+    gio::{self, prelude::*},
+    glib::{self, Priority},
+};
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+const RESTART_BASE: Duration = Duration::from_millis(500);
+const RESTART_MAX: Duration = Duration::from_secs(30);
+
+// a run this long counts as healthy, the next crash starts the backoff over
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// time elephant gets to exit on SIGTERM before it is killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CHILD: RefCell<Option<gio::Subprocess>> = const { RefCell::new(None) };
+}
+
+pub fn start(app: &Application) {
+    // quitting the app is what stops elephant, so signals have to go through it
+    for signal in [SIGINT, SIGTERM] {
+        let app = app.clone();
+        glib::unix_signal_add_local(signal, move || {
+            app.quit();
+            glib::ControlFlow::Break
+        });
+    }
+
+    glib::spawn_future_local(supervise());
+}
+
+async fn supervise() {
+    let mut retry = Backoff::new(RESTART_BASE, RESTART_MAX);
+
+    while !STOPPING.load(Ordering::SeqCst) {
+        let started = Instant::now();
+
+        match gio::Subprocess::newv(&[OsStr::new("elephant")], gio::SubprocessFlags::STDERR_PIPE) {
+            Ok(child) => {
+                println!("started elephant.");
+
+                CHILD.set(Some(child.clone()));
+
+                if let Some(stderr) = child.stderr_pipe() {
+                    glib::spawn_future_local(forward(stderr));
+                }
+
+                let _ = child.wait_future().await;
+                CHILD.set(None);
+
+                if STOPPING.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                if child.has_signaled() {
+                    eprintln!("elephant was killed by signal {}.", child.term_sig());
+                } else {
+                    eprintln!("elephant exited with status {}.", child.exit_status());
+                }
+            }
+            Err(e) => eprintln!("couldn't start elephant: {e}"),
+        }
+
+        if started.elapsed() > STABLE_AFTER {
+            retry = Backoff::new(RESTART_BASE, RESTART_MAX);
+        }
+
+        glib::timeout_future(retry.next_delay()).await;
+    }
+}
+
+async fn forward(stderr: gio::InputStream) {
+    let stream = gio::DataInputStream::new(&stderr);
+
+    while let Ok(Some(line)) = stream.read_line_utf8_future(Priority::DEFAULT).await {
+        eprintln!("elephant: {line}");
+    }
+}
+
+// once the main loop has stopped. Killed if it doesn't exit in time.
+pub fn stop() {
+    STOPPING.store(true, Ordering::SeqCst);
+
+    let Some(child) = CHILD.take() else {
+        return;
+    };
+
+    child.send_signal(SIGTERM);
+
+    let cancellable = gio::Cancellable::new();
+    let timeout = cancellable.clone();
+
+    thread::spawn(move || {
+        thread::sleep(SHUTDOWN_TIMEOUT);
+        timeout.cancel();
+    });
+
+    if child.wait(Some(&cancellable)).is_err() {
+        eprintln!("elephant didn't stop in time, killing it.");
+        child.force_exit();
+        let _ = child.wait(None::<&gio::Cancellable>);
+    }
+}
@@ -1,8 +1,9 @@
 use crate::config::get_config;
 use crate::providers::PROVIDERS;
+use crate::state::is_password;
 use gtk4::gdk::{self, Key};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{LazyLock, RwLock};
 
 pub const ACTION_CLOSE: &str = "%CLOSE%";
@@ -11,6 +12,8 @@ pub const ACTION_SELECT_PREVIOUS: &str = "%PREVIOUS%";
 pub const ACTION_TOGGLE_EXACT: &str = "%TOGGLE_EXACT%";
 pub const ACTION_RESUME_LAST_QUERY: &str = "%RESUME_LAST_QUERY%";
 pub const ACTION_QUICK_ACTIVATE: &str = "%QUICK_ACTIVATE%";
+pub const ACTION_TOGGLE_MARK: &str = "%TOGGLE_MARK%";
+pub const ACTION_MARK_ALL: &str = "%MARK_ALL%";
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub enum AfterAction {
@@ -55,6 +58,8 @@ static PROVIDER_BINDS: LazyLock<
 static PROVIDER_GLOBAL_BINDS: LazyLock<
     RwLock<HashMap<String, HashMap<Key, HashMap<gdk::ModifierType, Vec<Action>>>>>,
 > = LazyLock::new(RwLock::default);
+static DMENU_CUSTOM_BINDS: LazyLock<RwLock<HashMap<Key, HashMap<gdk::ModifierType, i32>>>> =
+    LazyLock::new(RwLock::default);
 
 pub static MODIFIERS: LazyLock<HashMap<&'static str, gdk::ModifierType>> = LazyLock::new(|| {
     let mut map = HashMap::new();
@@ -149,6 +154,36 @@ pub fn setup_binds() {
         .unwrap();
     });
 
+    config.keybinds.toggle_mark.iter().for_each(|b| {
+        parse_bind(
+            &Action {
+                action: ACTION_TOGGLE_MARK.to_string(),
+                bind: Some(b.clone()),
+                default: None,
+                global: Some(true),
+                label: Some("mark".to_string()),
+                after: Some(AfterAction::Nothing),
+            },
+            "",
+        )
+        .unwrap();
+    });
+
+    config.keybinds.mark_all.iter().for_each(|b| {
+        parse_bind(
+            &Action {
+                action: ACTION_MARK_ALL.to_string(),
+                bind: Some(b.clone()),
+                default: None,
+                global: Some(true),
+                label: Some("mark all".to_string()),
+                after: Some(AfterAction::Nothing),
+            },
+            "",
+        )
+        .unwrap();
+    });
+
     if let Some(qa) = &config.keybinds.quick_activate {
         qa.iter().enumerate().for_each(|(k, s)| {
             let action_str = format!("{ACTION_QUICK_ACTIVATE}:{k}");
@@ -178,39 +213,14 @@ fn parse_bind(b: &Action, provider: &str) -> Result<(), Box<dyn std::error::Erro
         b.action = first.to_string();
     }
 
-    let mut fields = b.bind.as_ref().unwrap().split_whitespace().peekable();
-
-    if fields.peek().is_none() {
-        return Err("incorrect bind".into());
-    }
-
-    let mut modifiers_list = Vec::new();
-    let mut key: Option<Key> = None;
-
-    for field in fields {
-        if let Some(&modifier) = MODIFIERS.get(field) {
-            modifiers_list.push(modifier);
-            continue;
+    let (key, modifier) = match parse_keys(b.bind.as_ref().unwrap()) {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!("Keybind Error: {e} in '{}'.", b.bind.as_ref().unwrap());
+            std::process::exit(1);
         }
+    };
 
-        key = match Key::from_name(field.to_string()) {
-            Some(k) => Some(k),
-            None => {
-                eprintln!(
-                    "Keybind Error: unable to create key from name: '{}' in '{}'.",
-                    field,
-                    b.bind.as_ref().unwrap()
-                );
-                std::process::exit(1);
-            }
-        };
-    }
-
-    let modifier = modifiers_list
-        .iter()
-        .fold(gdk::ModifierType::empty(), |acc, &m| acc | m);
-
-    let key = key.ok_or("incorrect bind")?;
     if provider.is_empty() {
         let mut binds = BINDS.write().unwrap();
         binds.entry(key).or_default().insert(modifier, b.clone());
@@ -243,8 +253,77 @@ fn parse_bind(b: &Action, provider: &str) -> Result<(), Box<dyn std::error::Erro
     Ok(())
 }
 
+fn parse_keys(bind: &str) -> Result<(Key, gdk::ModifierType), Box<dyn std::error::Error>> {
+    let mut fields = bind.split_whitespace().peekable();
+
+    if fields.peek().is_none() {
+        return Err("incorrect bind".into());
+    }
+
+    let mut modifiers_list = Vec::new();
+    let mut key: Option<Key> = None;
+
+    for field in fields {
+        if let Some(&modifier) = MODIFIERS.get(field) {
+            modifiers_list.push(modifier);
+            continue;
+        }
+
+        key = match Key::from_name(field.to_string()) {
+            Some(k) => Some(k),
+            None => return Err(format!("unable to create key from name: '{field}'").into()),
+        };
+    }
+
+    let modifier = modifiers_list
+        .iter()
+        .fold(gdk::ModifierType::empty(), |acc, &m| acc | m);
+
+    let key = key.ok_or("incorrect bind")?;
+
+    Ok((key, modifier))
+}
+
+// each accepts the selection like Return, but exits with its own code
+pub fn set_dmenu_custom_binds(binds: &[(String, i32)]) -> Result<(), String> {
+    let mut parsed: HashMap<Key, HashMap<gdk::ModifierType, i32>> = HashMap::new();
+    let mut codes = HashSet::new();
+
+    for (bind, code) in binds {
+        if !codes.insert(*code) {
+            return Err(format!(
+                "exit code {code} is bound twice, again to '{bind}'"
+            ));
+        }
+
+        let (key, modifier) = parse_keys(bind).map_err(|e| format!("{e} in '{bind}'"))?;
+
+        if parsed
+            .entry(key)
+            .or_default()
+            .insert(modifier, *code)
+            .is_some()
+        {
+            return Err(format!("'{bind}' is bound twice"));
+        }
+    }
+
+    *DMENU_CUSTOM_BINDS.write().unwrap() = parsed;
+    Ok(())
+}
+
+pub fn get_dmenu_custom_bind(key: Key, modifier: gdk::ModifierType) -> Option<i32> {
+    DMENU_CUSTOM_BINDS
+        .read()
+        .ok()?
+        .get(&key.to_lower())?
+        .get(&modifier)
+        .copied()
+}
+
 pub fn get_bind(key: Key, modifier: gdk::ModifierType) -> Option<Action> {
-    if get_config().debug {
+    // keys typed into a password would give it away
+    if get_config().debug && !is_password() {
         if modifier != gdk::ModifierType::empty() {
             let mut modifiers = Vec::new();
 
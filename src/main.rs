@@ -1,5 +1,6 @@
 mod config;
 mod data;
+mod elephant;
 mod keybinds;
 mod preview;
 mod protos;
@@ -8,29 +9,31 @@ mod renderers;
 mod state;
 mod theme;
 mod ui;
-use gtk4::gio::prelude::{ApplicationCommandLineExt, DataInputStreamExtManual, SettingsExt};
+use gtk4::gio::prelude::{
+    ApplicationCommandLineExt, BufferedInputStreamExt, DataInputStreamExtManual, SettingsExt,
+};
 use gtk4::gio::{self, ApplicationCommandLine, ApplicationHoldGuard};
-use gtk4::glib::Priority;
 use gtk4::glib::object::ObjectExt;
+use gtk4::glib::{JoinHandle, Priority};
 use gtk4::prelude::EntryExt;
 
 use config::get_config;
 use state::init_app_state;
 use which::which;
 
-use std::cell::OnceCell;
+use std::cell::{OnceCell, RefCell};
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixListener;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::rc::Rc;
 use std::sync::RwLock;
 use std::thread;
-use std::{env, fs};
+use std::{env, fs, mem};
 use tokio::sync::oneshot::{self, Sender};
 
 use gtk4::{
-    Application,
+    Application, InputPurpose,
     gio::{
         ApplicationFlags,
         prelude::{ApplicationExt, ApplicationExtManual},
@@ -39,33 +42,39 @@ use gtk4::{
     prelude::WidgetExt,
 };
 
-use crate::data::init_socket;
-use crate::keybinds::setup_binds;
+use crate::data::{init_socket, primary_socket};
+use crate::keybinds::{set_dmenu_custom_binds, setup_binds};
 use crate::protos::QueryResponseObject;
-use crate::protos::generated_proto::query::{QueryResponse, query_response};
+use crate::providers::dmenu::{self, Fields, Format, Match};
 use crate::providers::setup_providers;
 use crate::state::{
-    get_parameter_height, get_parameter_max_height, get_parameter_max_width,
-    get_parameter_min_height, get_parameter_min_width, get_parameter_width, get_placeholder,
-    get_provider, get_theme, has_elephant, has_theme, is_connected, is_dmenu, is_dmenu_keep_open,
-    is_input_only, is_no_hints, is_no_search, is_param_close, is_service, is_visible,
-    set_current_set, set_dmenu_current, set_dmenu_exit_after, set_dmenu_keep_open,
-    set_has_elephant, set_hide_qa, set_initial_height, set_initial_max_height,
+    can_query, get_dmenu_delimiter, get_dmenu_with_nth, get_parameter_height,
+    get_parameter_max_height, get_parameter_max_width, get_parameter_min_height,
+    get_parameter_min_width, get_parameter_width, get_placeholder, get_provider, get_theme,
+    has_elephant, has_theme, is_connected, is_dmenu, is_dmenu_json, is_dmenu_keep_open,
+    is_input_only, is_no_hints, is_no_search, is_param_close, is_password, is_service, is_visible,
+    set_current_set, set_dmenu_accept_nth, set_dmenu_case_sensitive, set_dmenu_current,
+    set_dmenu_delimiter, set_dmenu_exit_after, set_dmenu_format, set_dmenu_json,
+    set_dmenu_keep_open, set_dmenu_match, set_dmenu_multi, set_dmenu_preview, set_dmenu_sort,
+    set_dmenu_with_nth, set_has_elephant, set_hide_qa, set_initial_height, set_initial_max_height,
     set_initial_max_width, set_initial_min_height, set_initial_min_width, set_initial_placeholder,
     set_initial_width, set_input_only, set_is_dmenu, set_is_service, set_is_visible, set_no_hints,
     set_no_search, set_param_close, set_parameter_height, set_parameter_max_height,
     set_parameter_max_width, set_parameter_min_height, set_parameter_min_width,
-    set_parameter_width, set_placeholder, set_provider, set_theme,
+    set_parameter_width, set_password, set_placeholder, set_provider, set_socket, set_theme,
 };
 use crate::theme::{setup_css, setup_css_provider, setup_themes};
+use crate::ui::dmenu_list;
 use crate::ui::window::{
-    handle_preview, quit, set_input_text, set_keybind_hint, setup_window, with_window,
+    handle_preview, quit, set_input_text, set_loading, setup_window, update_sections, with_window,
 };
 
-static GLOBAL_DMENU_SENDER: RwLock<Option<Sender<String>>> = RwLock::new(None);
+// dmenu's output and the exit status of the waiting command line
+static GLOBAL_DMENU_SENDER: RwLock<Option<Sender<(String, i32)>>> = RwLock::new(None);
 
 thread_local! {
     static HOLD_GUARD: OnceCell<ApplicationHoldGuard> = OnceCell::new();
+    static DMENU_READER: RefCell<Option<JoinHandle<()>>> = const { RefCell::new(None) };
 }
 
 fn main() -> glib::ExitCode {
@@ -82,7 +91,11 @@ fn main() -> glib::ExitCode {
     app.connect_activate(activate);
     app.connect_startup(startup);
 
-    app.run()
+    let code = app.run();
+
+    elephant::supervisor::stop();
+
+    code
 }
 
 fn init_ui(app: &Application, dmenu: bool) {
@@ -105,6 +118,12 @@ fn init_ui(app: &Application, dmenu: bool) {
     if !dmenu || is_service() {
         elephant = which("elephant").is_ok();
         set_has_elephant(elephant);
+
+        if !elephant {
+            println!("elephant not found, falling back to the built-in desktop applications.");
+        } else if is_service() && get_config().elephant.autostart {
+            elephant::supervisor::start(app);
+        }
     }
 
     setup_providers(elephant);
@@ -139,11 +158,24 @@ fn adjust_color_scheme(settings: gio::Settings) {
     });
 }
 
+// f.e. when walker closes before a streaming producer is done
+fn stop_dmenu_input() {
+    if let Some(handle) = DMENU_READER.with(|reader| reader.borrow_mut().take()) {
+        handle.abort();
+    }
+
+    set_loading(false);
+}
+
 fn send_message(message: String) {
+    send_message_with_status(message, 0);
+}
+
+fn send_message_with_status(message: String, status: i32) {
     let mut sender_guard = GLOBAL_DMENU_SENDER.write().unwrap();
 
     if let Some(sender) = sender_guard.take() {
-        if let Err(_) = sender.send(message) {
+        if let Err(_) = sender.send((message, status)) {
             println!("the receiver dropped");
         }
 
@@ -190,6 +222,15 @@ fn add_flags(app: &Application) {
         None,
     );
 
+    app.add_main_option(
+        "password",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::None,
+        "mask the input and don't remember it, f.e. with --inputonly for a password prompt. dmenu only.",
+        None,
+    );
+
     app.add_main_option(
         "provider",
         b'm'.into(),
@@ -289,6 +330,15 @@ fn add_flags(app: &Application) {
         None,
     );
 
+    app.add_main_option(
+        "socket",
+        b'S'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "elephant socket to connect to, fixed once walker runs",
+        None,
+    );
+
     app.add_main_option(
         "dmenu",
         b'd'.into(),
@@ -333,6 +383,105 @@ fn add_flags(app: &Application) {
         "exit after this dmenu call. only when using service. dmenu only",
         None,
     );
+
+    app.add_main_option(
+        "delimiter",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "field delimiter for --with-nth and --accept-nth, defaults to tab. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "with-nth",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "fields to show and match on, f.e. '2..' or '1,3'. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "accept-nth",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "fields to print on selection, the whole line if unset. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "format",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "what to print on selection: text, index, query or json. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "json",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::None,
+        "read lines as json objects with text, subtext, icon, preview, state and actions. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "match",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "how lines are matched: fuzzy, exact, prefix, regex or tokens. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "case-sensitive",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::None,
+        "match case sensitively. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "no-sort",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::None,
+        "keep the input order instead of sorting matches by score. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "preview",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::String,
+        "command previewing the selected line, '{}' is replaced with it. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "kb-custom",
+        b'\0'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::StringArray,
+        "bind accepting the selection with exit code 10, repeat for 11, 12... numbered after dmenu.custom_binds. dmenu only.",
+        None,
+    );
+
+    app.add_main_option(
+        "multi",
+        b'M'.into(),
+        OptionFlags::NONE,
+        glib::OptionArg::None,
+        "allow marking several lines, all of them are returned. dmenu only.",
+        None,
+    );
 }
 
 fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
@@ -361,6 +510,21 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
         }
     }
 
+    if let Some(val) = options.lookup_value("socket", Some(VariantTy::STRING)) {
+        let socket = val.str().unwrap();
+
+        // elephant is connected to once per process, a running walker keeps its socket
+        if cmd.is_remote() && Path::new(socket) != primary_socket() {
+            cmd.printerr_literal(&format!(
+                "--socket can't change the socket of a running walker, it uses {}\n",
+                primary_socket().display()
+            ));
+            return 1;
+        }
+
+        set_socket(socket.to_string());
+    }
+
     if let Some(val) = options.lookup_value("set", Some(VariantTy::STRING)) {
         let set = val.str().unwrap();
         set_current_set(set.to_string());
@@ -396,9 +560,104 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
     'dmenu: {
         if !options.contains("dmenu") {
             set_dmenu_keep_open(false);
+            set_dmenu_multi(false);
+            set_password(false);
             set_is_dmenu(false);
             break 'dmenu;
         } else {
+            let fields = |name: &str| {
+                options
+                    .lookup_value(name, Some(VariantTy::STRING))
+                    .map(|v| Fields::parse(v.str().unwrap()))
+                    .transpose()
+            };
+
+            match (fields("with-nth"), fields("accept-nth")) {
+                (Ok(with_nth), Ok(accept_nth)) => {
+                    set_dmenu_with_nth(with_nth);
+                    set_dmenu_accept_nth(accept_nth);
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    cmd.printerr_literal(&format!("{e}\n"));
+                    return 1;
+                }
+            }
+
+            let format = options
+                .lookup_value("format", Some(VariantTy::STRING))
+                .map(|v| Format::parse(v.str().unwrap()))
+                .transpose();
+
+            match format {
+                Ok(format) => set_dmenu_format(format.unwrap_or_default()),
+                Err(e) => {
+                    cmd.printerr_literal(&format!("{e}\n"));
+                    return 1;
+                }
+            }
+
+            let cfg = get_config();
+
+            let matching = options
+                .lookup_value("match", Some(VariantTy::STRING))
+                .map(|v| Match::parse(v.str().unwrap()))
+                .transpose();
+
+            match matching {
+                Ok(matching) => set_dmenu_match(matching.unwrap_or(cfg.dmenu.matching)),
+                Err(e) => {
+                    cmd.printerr_literal(&format!("{e}\n"));
+                    return 1;
+                }
+            }
+
+            set_dmenu_case_sensitive(
+                cfg.dmenu.case_sensitive || options.contains("case-sensitive"),
+            );
+            set_dmenu_sort(cfg.dmenu.sort && !options.contains("no-sort"));
+
+            let delimiter = options
+                .lookup_value("delimiter", Some(VariantTy::STRING))
+                .map(|v| v.str().unwrap().replace("\\t", "\t"))
+                .unwrap_or_else(|| "\t".to_string());
+
+            set_dmenu_delimiter(delimiter);
+
+            set_dmenu_preview(
+                options
+                    .lookup_value("preview", Some(VariantTy::STRING))
+                    .map(|v| v.str().unwrap().to_string())
+                    .unwrap_or_default(),
+            );
+
+            let mut custom_binds: Vec<(String, i32)> = cfg
+                .dmenu
+                .custom_binds
+                .iter()
+                .map(|(bind, code)| (bind.clone(), *code))
+                .collect();
+
+            custom_binds.sort_by_key(|&(_, code)| code);
+
+            // numbered after the configured ones, so they can't collide
+            let first = custom_binds
+                .last()
+                .map_or(10, |&(_, code)| (code + 1).max(10));
+
+            if let Some(val) = options.lookup_value("kb-custom", Some(VariantTy::STRING_ARRAY)) {
+                custom_binds.extend(
+                    val.get::<Vec<String>>()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .zip(first..),
+                );
+            }
+
+            if let Err(e) = set_dmenu_custom_binds(&custom_binds) {
+                cmd.printerr_literal(&format!("{e}\n"));
+                return 1;
+            }
+
             set_is_dmenu(true);
         }
 
@@ -407,6 +666,9 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
         }
 
         set_input_only(options.contains("inputonly"));
+        set_password(options.contains("password"));
+        set_dmenu_multi(options.contains("multi"));
+        set_dmenu_json(options.contains("json"));
 
         if options.contains("keepopen") && app.flags().contains(ApplicationFlags::IS_SERVICE) {
             set_dmenu_keep_open(true);
@@ -423,11 +685,10 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
             break 'dmenu;
         }
 
-        with_window(|w| {
+        with_window(|_| {
             set_input_text("");
 
-            let items = w.items.clone();
-            items.remove_all();
+            dmenu_list::reset();
 
             if is_input_only() {
                 return;
@@ -436,48 +697,81 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
             let stdin = cmd.stdin();
             let data_stream = gio::DataInputStream::new(&stdin.unwrap());
 
-            async fn read_lines_async(stream: Rc<gio::DataInputStream>, items: gio::ListStore) {
+            async fn read_lines_async(stream: Rc<gio::DataInputStream>) {
                 let mut i = 0;
+                let with_nth = get_dmenu_with_nth();
+                let delimiter = get_dmenu_delimiter();
+
+                let json = is_dmenu_json();
+
+                let mut batch = Vec::new();
 
                 loop {
-                    match stream.read_line_utf8_future(Priority::DEFAULT).await {
-                        Ok(Some(line)) => {
-                            if line.is_empty() {
-                                break;
-                            }
+                    // bytes, rofi's row options follow a \0
+                    let line = match stream.read_line_future(Priority::DEFAULT).await {
+                        Ok(line) => line,
+                        Err(e) => {
+                            eprintln!("Error reading: {e}");
+                            break;
+                        }
+                    };
+
+                    // an empty line and EOF look the same, at EOF there's nothing left to read
+                    if line.is_empty()
+                        && stream.available() == 0
+                        && !matches!(stream.fill_future(1, Priority::DEFAULT).await, Ok(n) if n > 0)
+                    {
+                        break;
+                    }
 
-                            let line = line.trim();
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim();
 
-                            if !line.is_empty() {
-                                let mut item = query_response::Item::new();
-                                item.text = line.to_string();
-                                item.provider = "dmenu".to_string();
-                                item.score = 1000000 - i;
-                                item.actions = vec!["select".to_string()];
+                    if !line.is_empty() {
+                        match dmenu::parse_line(line, json) {
+                            Ok(mut line) => {
+                                let item = &mut line.item;
 
-                                let mut response = QueryResponse::new();
-                                response.item = protobuf::MessageField::some(item);
+                                // the full line is returned, what's shown might only be some fields
+                                if let Some(fields) = &with_nth
+                                    && item.text == item.identifier
+                                {
+                                    item.text = fields.select(&item.identifier, &delimiter);
+                                }
 
-                                items.append(&QueryResponseObject::new(response));
-                            }
+                                if item.actions.is_empty() {
+                                    item.actions = vec!["select".to_string()];
+                                }
 
-                            i += 1;
-                        }
-                        Ok(None) => {
-                            set_keybind_hint();
-                            break;
-                        }
-                        Err(e) => {
-                            eprintln!("Error reading: {e}");
-                            break;
+                                item.provider = "dmenu".to_string();
+                                item.score = dmenu::BASE_SCORE - i;
+
+                                batch.push(line);
+                            }
+                            Err(e) => eprintln!("invalid line {}: {e}", i + 1),
                         }
                     }
+
+                    i += 1;
+
+                    // show what's there before waiting for a slow producer
+                    if stream.available() == 0 && !batch.is_empty() {
+                        dmenu_list::push(mem::take(&mut batch));
+                    }
                 }
+
+                dmenu_list::push(batch);
+                set_loading(false);
             }
 
-            glib::spawn_future_local(async move {
-                read_lines_async(Rc::new(data_stream), items).await;
+            stop_dmenu_input();
+            set_loading(true);
+
+            let handle = glib::spawn_future_local(async move {
+                read_lines_async(Rc::new(data_stream)).await;
             });
+
+            DMENU_READER.with(|reader| *reader.borrow_mut() = Some(handle));
         });
 
         if !is_service() {
@@ -492,11 +786,14 @@ fn handle_command_line(app: &Application, cmd: &ApplicationCommandLine) -> i32 {
 
         glib::spawn_future_local(async move {
             match receiver.await {
-                Ok(message) => match message.as_str() {
+                Ok((message, status)) => match message.as_str() {
                     "CNCLD" => {
                         cmd.set_exit_status(130);
                     }
-                    msg => cmd.print_literal(&format!("{msg}\n")),
+                    msg => {
+                        cmd.print_literal(&format!("{msg}\n"));
+                        cmd.set_exit_status(status);
+                    }
                 },
                 Err(_) => {
                     println!("the sender dropped");
@@ -536,6 +833,15 @@ fn activate(app: &Application) {
     };
 
     with_window(|w| {
+        if let Some(input) = &w.input {
+            input.set_visibility(!is_password());
+            input.set_input_purpose(if is_password() {
+                InputPurpose::Password
+            } else {
+                InputPurpose::FreeForm
+            });
+        }
+
         if is_input_only() {
             w.content_container.set_visible(false);
             if let Some(keybinds) = &w.keybinds {
@@ -618,6 +924,7 @@ fn activate(app: &Application) {
         }
 
         setup_css(get_theme());
+        update_sections(w);
 
         if let Some(input) = &w.input {
             if is_service() && provider != "default" {
@@ -627,7 +934,7 @@ fn activate(app: &Application) {
             input.grab_focus();
         }
 
-        if !is_connected() && !is_dmenu() {
+        if !can_query() && !is_dmenu() {
             w.elephant_hint.set_visible(true);
             w.scroll.set_visible(false);
         } else {
@@ -637,10 +944,13 @@ fn activate(app: &Application) {
         w.window.set_visible(true);
 
         if !is_dmenu() && !is_connected() && has_elephant() {
-            thread::spawn(|| init_socket().unwrap());
-        } else if !has_elephant() && !is_dmenu() {
-            println!("Please install elephant.");
-            process::exit(1);
+            thread::spawn(init_socket);
+        } else if !has_elephant()
+            && !is_dmenu()
+            && let Some(input) = &w.input
+        {
+            // the built-in providers answer right away
+            input.emit_by_name::<()>("changed", &[]);
         }
     });
 
@@ -0,0 +1,390 @@
+use super::PreviewHandler;
+use super::files_preview::FilePreview;
+use crate::protos::generated_proto::query::query_response::Item;
+use crate::state::get_dmenu_preview;
+use gtk4::gio::{Subprocess, SubprocessFlags};
+use gtk4::glib;
+use gtk4::{Box as GtkBox, Builder, prelude::*};
+use std::cell::{Cell, RefCell};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::rc::Rc;
+
+const MAX_OUTPUT: usize = 1024 * 1024; // 1MB
+const READ_CHUNK: usize = 64 * 1024;
+
+// output of --preview for dmenu lines, or the file the line's preview names
+#[derive(Debug, Default)]
+pub struct CommandPreviewHandler {
+    cached_preview: Rc<RefCell<Option<FilePreview>>>,
+    running: RefCell<Option<Subprocess>>,
+    generation: Rc<Cell<u64>>,
+}
+
+impl CommandPreviewHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // output of a command for a line that's no longer selected is dropped
+    fn cancel(&self) {
+        self.generation.set(self.generation.get() + 1);
+
+        if let Some(process) = self.running.borrow_mut().take() {
+            process.force_exit();
+        }
+    }
+}
+
+impl PreviewHandler for CommandPreviewHandler {
+    fn clear_cache(&self) {
+        self.cancel();
+
+        let mut cached_preview = self.cached_preview.borrow_mut();
+        if let Some(preview) = cached_preview.as_mut() {
+            preview.clear_preview();
+        }
+        *cached_preview = None;
+    }
+
+    fn handle(&self, item: &Item, preview: &GtkBox, builder: &Builder) {
+        self.cancel();
+
+        let command = get_dmenu_preview();
+
+        if command.is_empty() {
+            show(&self.cached_preview, preview, builder, |p| {
+                p.preview_file(&item.preview).is_ok()
+            });
+            return;
+        }
+
+        let line = if item.identifier.is_empty() {
+            &item.text
+        } else {
+            &item.identifier
+        };
+
+        let command = command.replace("{}", &shell_quote(line));
+
+        let process = match Subprocess::newv(
+            &[OsStr::new("sh"), OsStr::new("-c"), OsStr::new(&command)],
+            SubprocessFlags::STDOUT_PIPE | SubprocessFlags::STDERR_MERGE,
+        ) {
+            Ok(process) => process,
+            Err(e) => {
+                eprintln!("preview command failed: {e}");
+                return;
+            }
+        };
+
+        *self.running.borrow_mut() = Some(process.clone());
+
+        let generation = self.generation.clone();
+        let current = generation.get();
+        let cached_preview = self.cached_preview.clone();
+        let preview = preview.clone();
+        let builder = builder.clone();
+
+        let Some(stdout) = process.stdout_pipe() else {
+            return;
+        };
+
+        glib::spawn_future_local(async move {
+            let mut output = Vec::new();
+
+            while output.len() < MAX_OUTPUT {
+                let Ok(chunk) = stdout
+                    .read_bytes_future(
+                        READ_CHUNK.min(MAX_OUTPUT - output.len()),
+                        glib::Priority::DEFAULT,
+                    )
+                    .await
+                else {
+                    return;
+                };
+
+                if chunk.is_empty() {
+                    break;
+                }
+
+                output.extend_from_slice(&chunk);
+            }
+
+            // the rest is never read, the command would block on it forever
+            process.force_exit();
+
+            if generation.get() != current {
+                return;
+            }
+
+            let output = String::from_utf8_lossy(&output);
+            let path = output.trim();
+
+            show(&cached_preview, &preview, &builder, |p| {
+                if !path.contains('\n') && Path::new(path).is_file() {
+                    return p.preview_file(path).is_ok();
+                }
+
+                p.preview_markup(&ansi_to_markup(&output));
+                true
+            });
+        });
+    }
+}
+
+fn show(
+    cached_preview: &RefCell<Option<FilePreview>>,
+    preview: &GtkBox,
+    builder: &Builder,
+    f: impl FnOnce(&mut FilePreview) -> bool,
+) {
+    let mut cached_preview = cached_preview.borrow_mut();
+
+    if cached_preview.is_none() {
+        *cached_preview = FilePreview::new_with_builder(builder)
+            .or_else(|_| FilePreview::new())
+            .ok();
+    }
+
+    let Some(file_preview) = cached_preview.as_mut() else {
+        return;
+    };
+
+    if !f(file_preview) {
+        return;
+    }
+
+    while let Some(child) = preview.first_child() {
+        child.unparent();
+    }
+
+    file_preview.box_widget.set_can_target(false);
+    preview.append(&file_preview.box_widget);
+    preview.set_visible(true);
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+impl Style {
+    // parameters of an SGR sequence, f.e. "1;38;5;208"
+    fn apply(&mut self, params: &str) {
+        let mut params = params.split(';').map(|p| p.parse::<u32>().unwrap_or(0));
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                9 => self.strikethrough = true,
+                21 | 22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.foreground = Some(PALETTE[(param - 30) as usize].to_string()),
+                38 => self.foreground = extended_color(&mut params),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(PALETTE[(param - 40) as usize].to_string()),
+                48 => self.background = extended_color(&mut params),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(PALETTE[(param - 82) as usize].to_string()),
+                100..=107 => self.background = Some(PALETTE[(param - 92) as usize].to_string()),
+                _ => (),
+            }
+        }
+    }
+
+    fn span(&self) -> String {
+        let mut span = String::from("<span");
+
+        if let Some(fg) = &self.foreground {
+            span.push_str(&format!(" foreground=\"{fg}\""));
+        }
+        if let Some(bg) = &self.background {
+            span.push_str(&format!(" background=\"{bg}\""));
+        }
+        if self.bold {
+            span.push_str(" weight=\"bold\"");
+        }
+        if self.dim {
+            span.push_str(" fgalpha=\"50%\"");
+        }
+        if self.italic {
+            span.push_str(" style=\"italic\"");
+        }
+        if self.underline {
+            span.push_str(" underline=\"single\"");
+        }
+        if self.strikethrough {
+            span.push_str(" strikethrough=\"true\"");
+        }
+
+        span.push('>');
+        span
+    }
+}
+
+// 38;5;n and 38;2;r;g;b
+fn extended_color(params: &mut impl Iterator<Item = u32>) -> Option<String> {
+    match params.next()? {
+        5 => {
+            let n = params.next()?;
+            let level = |v: u32| if v == 0 { 0 } else { 55 + v * 40 };
+
+            match n {
+                0..=15 => Some(PALETTE[n as usize].to_string()),
+                16..=231 => {
+                    let n = n - 16;
+                    Some(format!(
+                        "#{:02x}{:02x}{:02x}",
+                        level(n / 36),
+                        level(n / 6 % 6),
+                        level(n % 6)
+                    ))
+                }
+                232..=255 => {
+                    let gray = 8 + (n - 232) * 10;
+                    Some(format!("#{gray:02x}{gray:02x}{gray:02x}"))
+                }
+                _ => None,
+            }
+        }
+        2 => {
+            let (r, g, b) = (params.next()?, params.next()?, params.next()?);
+            Some(format!(
+                "#{:02x}{:02x}{:02x}",
+                r.min(255),
+                g.min(255),
+                b.min(255)
+            ))
+        }
+        _ => None,
+    }
+}
+
+// other escape sequences than colors and styles are dropped
+pub fn ansi_to_markup(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut style = Style::default();
+    let mut open = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    let mut end = None;
+
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            end = Some(c);
+                            break;
+                        }
+
+                        params.push(c);
+                    }
+
+                    if end != Some('m') {
+                        continue;
+                    }
+
+                    style.apply(&params);
+
+                    if open {
+                        out.push_str("</span>");
+                    }
+
+                    open = style != Style::default();
+
+                    if open {
+                        out.push_str(&style.span());
+                    }
+                }
+                // OSC, ends with BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+
+                        if c == '\x1b' {
+                            chars.next_if_eq(&'\\');
+                            break;
+                        }
+                    }
+                }
+                _ => (),
+            },
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => (),
+            c => out.push(c),
+        }
+    }
+
+    if open {
+        out.push_str("</span>");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_is_escaped() {
+        assert_eq!(ansi_to_markup("a <b> & c\r\n"), "a &lt;b&gt; &amp; c\n");
+    }
+
+    #[test]
+    fn colors_and_reset() {
+        assert_eq!(
+            ansi_to_markup("\x1b[1;31merror\x1b[0m: x"),
+            "<span foreground=\"#cd0000\" weight=\"bold\">error</span>: x"
+        );
+        assert_eq!(
+            ansi_to_markup("\x1b[38;5;208ma\x1b[38;2;1;2;3mb\x1b[39mc"),
+            "<span foreground=\"#ff8700\">a</span><span foreground=\"#010203\">b</span>c"
+        );
+    }
+
+    #[test]
+    fn other_sequences_are_dropped() {
+        assert_eq!(
+            ansi_to_markup("\x1b]8;;file:///a\x1b\\a\x1b[2Kb\x1b[m"),
+            "ab"
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -168,7 +168,7 @@ impl FilePreview {
         function(self, file_path)
     }
 
-    fn clear_preview(&self) {
+    pub fn clear_preview(&self) {
         while let Some(child) = self.preview_area.first_child() {
             if let Some(picture) = child.downcast_ref::<Picture>() {
                 picture.set_filename(Option::<&str>::None);
@@ -363,6 +363,27 @@ impl FilePreview {
         Ok(())
     }
 
+    pub fn preview_markup(&mut self, markup: &str) {
+        self.current_file = String::new();
+        self.clear_preview();
+
+        let text_view = TextView::new();
+        text_view.set_editable(false);
+        text_view.set_monospace(true);
+        text_view.set_wrap_mode(WrapMode::Word);
+        text_view.set_size_request(300, 200);
+        let buffer = text_view.buffer();
+        buffer.insert_markup(&mut buffer.end_iter(), markup);
+
+        let scrolled = ScrolledWindow::new();
+        scrolled.set_child(Some(&text_view));
+        scrolled.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+        scrolled.set_size_request(300, 250);
+
+        self.preview_area.add_child(&scrolled);
+        self.preview_area.set_visible_child(&scrolled);
+    }
+
     fn preview_video(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let player = Video::for_filename(Some(file_path));
         player.set_autoplay(true);
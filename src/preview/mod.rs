@@ -1,6 +1,8 @@
+mod command_preview;
 mod files_preview;
 
 use crate::protos::generated_proto::query::query_response::Item;
+pub use command_preview::CommandPreviewHandler;
 pub use files_preview::FilesPreviewHandler;
 use gtk4::{Box as GtkBox, Builder};
 use std::cell::LazyCell;
@@ -17,6 +19,7 @@ thread_local! {
         let mut previewers: HashMap<String, Box<dyn PreviewHandler>> = HashMap::new();
         previewers.insert("files".to_string(), Box::new(FilesPreviewHandler::new()));
         previewers.insert("menus".to_string(), Box::new(FilesPreviewHandler::new()));
+        previewers.insert("dmenu".to_string(), Box::new(CommandPreviewHandler::new()));
         previewers
     });
 }
@@ -15,7 +15,7 @@ mod imp {
     #[derive(Debug, Default)]
     pub struct QueryResponseObject {
         pub response: RefCell<Option<QueryResponse>>,
-        pub dmenu_score: RefCell<u32>,
+        pub marked: RefCell<bool>,
     }
 
     #[glib::object_subclass]
@@ -42,11 +42,11 @@ impl QueryResponseObject {
         self.imp().response.borrow().as_ref().unwrap().clone()
     }
 
-    pub fn dmenu_score(&self) -> u32 {
-        *self.imp().dmenu_score.borrow()
+    pub fn is_marked(&self) -> bool {
+        *self.imp().marked.borrow()
     }
 
-    pub fn set_dmenu_score(&self, val: u32) {
-        *self.imp().dmenu_score.borrow_mut() = val;
+    pub fn set_marked(&self, val: bool) {
+        *self.imp().marked.borrow_mut() = val;
     }
 }
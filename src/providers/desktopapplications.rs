@@ -0,0 +1,444 @@
+// stand-in for elephant's desktopapplications, so walker launches apps without it
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::{env, fs};
+
+use gtk4::{
+    gdk::{self, prelude::DisplayExt},
+    gio::{self, prelude::AppInfoExt},
+};
+use nucleo_matcher::pattern::{AtomKind, CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32Str};
+use protobuf::MessageField;
+
+use crate::renderers::highlight::fuzzy_info;
+use crate::{protos::generated_proto::query::query_response::Item, providers::Provider};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct DesktopEntry {
+    id: String,
+    name: String,
+    generic_name: String,
+    comment: String,
+    icon: String,
+    keywords: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct DesktopApplications {
+    name: &'static str,
+    entries: OnceLock<Vec<DesktopEntry>>,
+}
+
+impl DesktopApplications {
+    pub fn new() -> Self {
+        Self {
+            name: "desktopapplications",
+            entries: OnceLock::new(),
+        }
+    }
+
+    fn entries(&self) -> &[DesktopEntry] {
+        self.entries.get_or_init(load_entries)
+    }
+}
+
+impl Provider for DesktopApplications {
+    fn get_name(&self) -> &str {
+        self.name
+    }
+
+    fn query(&self, query: &str, exact: bool) -> Option<Vec<Item>> {
+        let entries = self.entries();
+
+        if query.is_empty() {
+            return Some(entries.iter().map(|e| item(e, 0)).collect());
+        }
+
+        let kind = if exact {
+            AtomKind::Substring
+        } else {
+            AtomKind::Fuzzy
+        };
+
+        let pattern = Pattern::new(query, CaseMatching::Ignore, Normalization::Smart, kind);
+        let mut matcher = Matcher::new(Config::DEFAULT);
+        let mut buf = Vec::new();
+
+        let mut score =
+            |haystack: &str| pattern.score(Utf32Str::new(haystack, &mut buf), &mut matcher);
+
+        let mut matches: Vec<(u32, &DesktopEntry)> = entries
+            .iter()
+            .filter_map(|e| {
+                // a hit on the name beats one on the description
+                let secondary = [&e.generic_name]
+                    .into_iter()
+                    .chain(&e.keywords)
+                    .filter_map(|h| score(h))
+                    .max()
+                    .map(|s| s / 2);
+
+                score(&e.name).max(secondary).map(|s| (s, e))
+            })
+            .collect();
+
+        matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.name.cmp(&b.1.name)));
+
+        Some(
+            matches
+                .into_iter()
+                .map(|(score, e)| {
+                    let mut item = item(e, score);
+                    let mut indices = Vec::new();
+
+                    if pattern
+                        .indices(Utf32Str::new(&e.name, &mut buf), &mut matcher, &mut indices)
+                        .is_some()
+                    {
+                        item.fuzzyinfo = MessageField::some(fuzzy_info("text", indices));
+                    }
+
+                    item
+                })
+                .collect(),
+        )
+    }
+
+    fn activate(&self, item: &Item, action: &str) {
+        // pinning and history are elephant features
+        if !action.starts_with("start") {
+            return;
+        }
+
+        let Some(info) = gio::DesktopAppInfo::new(&item.identifier) else {
+            eprintln!("couldn't find application: {}", item.identifier);
+            return;
+        };
+
+        let context = gdk::Display::default().map(|d| d.app_launch_context());
+
+        if let Err(e) = info.launch(&[], context.as_ref()) {
+            eprintln!("couldn't launch {}: {e}", item.identifier);
+        }
+    }
+}
+
+fn item(entry: &DesktopEntry, score: u32) -> Item {
+    let mut item = Item::new();
+    item.identifier = entry.id.clone();
+    item.text = entry.name.clone();
+    item.subtext = if entry.comment.is_empty() {
+        entry.generic_name.clone()
+    } else {
+        entry.comment.clone()
+    };
+    item.icon = entry.icon.clone();
+    item.provider = "desktopapplications".to_string();
+    item.score = i32::try_from(score).unwrap_or(i32::MAX);
+    item.actions = vec!["start".to_string()];
+    item
+}
+
+fn load_entries() -> Vec<DesktopEntry> {
+    let dirs = xdg::BaseDirectories::new();
+
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let locales = locales();
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+
+    // earlier data dirs take precedence, so a user can hide or override system entries
+    for dir in dirs.get_data_home().into_iter().chain(dirs.get_data_dirs()) {
+        let root = dir.join("applications");
+        collect(&root, &root, &desktops, &locales, &mut seen, &mut entries);
+    }
+
+    entries.sort_by_key(|e| e.name.to_lowercase());
+    entries
+}
+
+fn collect(
+    root: &Path,
+    dir: &Path,
+    desktops: &[String],
+    locales: &[String],
+    seen: &mut HashSet<String>,
+    entries: &mut Vec<DesktopEntry>,
+) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in read_dir.flatten().map(|e| e.path()) {
+        if path.is_dir() {
+            collect(root, &path, desktops, locales, seen, entries);
+            continue;
+        }
+
+        if path.extension().is_none_or(|ext| ext != "desktop") {
+            continue;
+        }
+
+        let Some(id) = desktop_id(root, &path) else {
+            continue;
+        };
+
+        // hidden entries still shadow the ones further down
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+
+        if let Ok(content) = fs::read_to_string(&path)
+            && let Some(entry) = parse(&id, &content, desktops, locales)
+        {
+            entries.push(entry);
+        }
+    }
+}
+
+// applications/kde/org.kde.foo.desktop has the id kde-org.kde.foo.desktop
+fn desktop_id(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+
+    Some(
+        relative
+            .iter()
+            .map(|c| c.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("-"),
+    )
+}
+
+fn locales() -> Vec<String> {
+    let Some(locale) = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|v| !v.is_empty())
+    else {
+        return Vec::new();
+    };
+
+    // de_DE.UTF-8@euro -> de_DE, de
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+
+    let mut result = vec![locale.to_string()];
+
+    if let Some((lang, _)) = locale.split_once('_') {
+        result.push(lang.to_string());
+    }
+
+    result
+}
+
+fn parse(id: &str, content: &str, desktops: &[String], locales: &[String]) -> Option<DesktopEntry> {
+    let mut fields: HashMap<&str, &str> = HashMap::new();
+    let mut in_entry = false;
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+
+        if in_entry && let Some((key, value)) = line.split_once('=') {
+            fields.entry(key.trim()).or_insert(value.trim());
+        }
+    }
+
+    let is_true = |key: &str| fields.get(key).is_some_and(|v| *v == "true");
+
+    let list = |key: &str| -> Vec<&str> {
+        fields
+            .get(key)
+            .map(|v| v.split(';').filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    };
+
+    let current = |listed: &[&str]| {
+        listed
+            .iter()
+            .any(|d| desktops.iter().any(|c| c.eq_ignore_ascii_case(d)))
+    };
+
+    if fields.get("Type") != Some(&"Application") || is_true("NoDisplay") || is_true("Hidden") {
+        return None;
+    }
+
+    let only_show_in = list("OnlyShowIn");
+
+    if (!only_show_in.is_empty() && !current(&only_show_in)) || current(&list("NotShowIn")) {
+        return None;
+    }
+
+    let localized = |key: &str| {
+        locales
+            .iter()
+            .find_map(|l| fields.get(format!("{key}[{l}]").as_str()))
+            .or_else(|| fields.get(key))
+            .map(|v| unescape(v))
+            .unwrap_or_default()
+    };
+
+    let name = localized("Name");
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(DesktopEntry {
+        id: id.to_string(),
+        name,
+        generic_name: localized("GenericName"),
+        comment: localized("Comment"),
+        icon: localized("Icon"),
+        keywords: localized("Keywords")
+            .split(';')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIREFOX: &str = "\
+[Desktop Entry]
+Type=Application
+Name=Firefox
+Name[de]=Feuerfuchs
+GenericName=Web Browser
+Comment=Browse the\\sWeb
+Icon=firefox
+Keywords=web;internet;
+
+[Desktop Action new-window]
+Name=New Window
+";
+
+    fn desktops(names: &[&str]) -> Vec<String> {
+        names.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_entries() {
+        let entry = parse("firefox.desktop", FIREFOX, &[], &[]).unwrap();
+
+        assert_eq!(
+            entry,
+            DesktopEntry {
+                id: "firefox.desktop".to_string(),
+                name: "Firefox".to_string(),
+                generic_name: "Web Browser".to_string(),
+                comment: "Browse the Web".to_string(),
+                icon: "firefox".to_string(),
+                keywords: vec!["web".to_string(), "internet".to_string()],
+            }
+        );
+
+        let localized = parse("firefox.desktop", FIREFOX, &[], &desktops(&["de_DE", "de"]));
+        assert_eq!(localized.unwrap().name, "Feuerfuchs");
+    }
+
+    #[test]
+    fn respects_visibility() {
+        let no_display = FIREFOX.replace("Type=Application", "Type=Application\nNoDisplay=true");
+        assert!(parse("a.desktop", &no_display, &[], &[]).is_none());
+
+        let gnome_only = FIREFOX.replace("Type=Application", "Type=Application\nOnlyShowIn=GNOME;");
+        assert!(parse("a.desktop", &gnome_only, &desktops(&["KDE"]), &[]).is_none());
+        assert!(
+            parse(
+                "a.desktop",
+                &gnome_only,
+                &desktops(&["ubuntu", "GNOME"]),
+                &[]
+            )
+            .is_some()
+        );
+
+        let not_kde = FIREFOX.replace("Type=Application", "Type=Application\nNotShowIn=KDE;");
+        assert!(parse("a.desktop", &not_kde, &desktops(&["KDE"]), &[]).is_none());
+
+        let link = FIREFOX.replace("Type=Application", "Type=Link");
+        assert!(parse("a.desktop", &link, &[], &[]).is_none());
+    }
+
+    #[test]
+    fn desktop_ids() {
+        assert_eq!(
+            desktop_id(
+                Path::new("/usr/share/applications"),
+                Path::new("/usr/share/applications/kde/org.kde.dolphin.desktop")
+            ),
+            Some("kde-org.kde.dolphin.desktop".to_string())
+        );
+    }
+
+    #[test]
+    fn ranks_name_matches_first() {
+        let provider = DesktopApplications::new();
+        let _ = provider.entries.set(vec![
+            DesktopEntry {
+                id: "chromium.desktop".to_string(),
+                name: "Chromium".to_string(),
+                generic_name: "Web Browser".to_string(),
+                ..Default::default()
+            },
+            DesktopEntry {
+                id: "webcam.desktop".to_string(),
+                name: "Webcam".to_string(),
+                ..Default::default()
+            },
+        ]);
+
+        let items = provider.query("web", false).unwrap();
+        let ids: Vec<&str> = items.iter().map(|i| i.identifier.as_str()).collect();
+        assert_eq!(ids, vec!["webcam.desktop", "chromium.desktop"]);
+
+        // only hits on the shown name get highlighted
+        assert_eq!(items[0].fuzzyinfo.positions, vec![0, 1, 2]);
+        assert!(items[1].fuzzyinfo.is_none());
+
+        assert!(provider.query("zzz", false).unwrap().is_empty());
+        assert_eq!(provider.query("", false).unwrap().len(), 2);
+    }
+}
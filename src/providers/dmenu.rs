@@ -1,4 +1,279 @@
+use crate::protos::generated_proto::query::query_response::Item;
 use crate::providers::Provider;
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32Str};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+// lines are scored by their position, so the input order is kept
+pub const BASE_SCORE: i32 = 1_000_000;
+
+pub fn index(item: &Item) -> i64 {
+    i64::from(BASE_SCORE) - i64::from(item.score)
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct JsonLine {
+    text: String,
+    subtext: String,
+    icon: String,
+    preview: String,
+    state: Vec<String>,
+    actions: Vec<String>,
+    info: String,
+    meta: String,
+}
+
+// info is only printed with --format json, meta is matched but not shown
+#[derive(Debug, Default)]
+pub struct Line {
+    pub item: Item,
+    pub info: String,
+    pub meta: String,
+}
+
+impl Line {
+    pub fn match_text(&self) -> Cow<'_, str> {
+        if self.meta.is_empty() {
+            Cow::Borrowed(&self.item.text)
+        } else {
+            Cow::Owned(format!("{} {}", self.item.text, self.meta))
+        }
+    }
+}
+
+// rofi's `text\0icon\x1ffirefox\x1f...` row options, or a JSON object with --json
+pub fn parse_line(line: &str, json: bool) -> Result<Line, String> {
+    let mut parsed = Line::default();
+    let item = &mut parsed.item;
+
+    if json {
+        let line: JsonLine = serde_json::from_str(line).map_err(|e| e.to_string())?;
+
+        item.identifier = line.text.clone();
+        item.text = line.text;
+        item.subtext = line.subtext;
+        item.icon = line.icon;
+        item.preview = line.preview;
+        item.state = line.state;
+        item.actions = line.actions;
+        parsed.info = line.info;
+        parsed.meta = line.meta;
+
+        return Ok(parsed);
+    }
+
+    let (text, options) = line.split_once('\0').unwrap_or((line, ""));
+
+    item.identifier = text.to_string();
+    item.text = text.to_string();
+
+    let mut options = options.split('\x1f');
+
+    while let (Some(key), Some(value)) = (options.next(), options.next()) {
+        match key {
+            "icon" => item.icon = value.to_string(),
+            "display" => item.text = value.to_string(),
+            "subtext" => item.subtext = value.to_string(),
+            "preview" => item.preview = value.to_string(),
+            "state" => item.state.extend(value.split(',').map(str::to_string)),
+            "actions" => item.actions.extend(value.split(',').map(str::to_string)),
+            // rofi's row flags become css classes
+            "active" | "urgent" if value == "true" => item.state.push(key.to_string()),
+            "info" => parsed.info = value.to_string(),
+            "meta" => parsed.meta = value.to_string(),
+            _ => (),
+        }
+    }
+
+    Ok(parsed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Index,
+    Query,
+    Json,
+}
+
+impl Format {
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val {
+            "text" => Ok(Format::Text),
+            "index" => Ok(Format::Index),
+            "query" => Ok(Format::Query),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "invalid format: '{val}', expected text, index, query or json"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Match {
+    #[default]
+    Fuzzy,
+    Exact,
+    Prefix,
+    Regex,
+    // every word of the query, in any order
+    Tokens,
+}
+
+impl Match {
+    pub fn parse(val: &str) -> Result<Self, String> {
+        match val {
+            "fuzzy" => Ok(Match::Fuzzy),
+            "exact" => Ok(Match::Exact),
+            "prefix" => Ok(Match::Prefix),
+            "regex" => Ok(Match::Regex),
+            "tokens" => Ok(Match::Tokens),
+            _ => Err(format!(
+                "invalid match: '{val}', expected fuzzy, exact, prefix, regex or tokens"
+            )),
+        }
+    }
+}
+
+enum Compiled {
+    Fuzzy {
+        matcher: Matcher,
+        pattern: Pattern,
+        threshold: u32,
+    },
+    Exact(Vec<char>),
+    Prefix(Vec<char>),
+    // an incomplete regex while typing matches nothing
+    Regex(Option<Regex>),
+    Tokens(Vec<Vec<char>>),
+}
+
+pub struct LineMatcher {
+    compiled: Compiled,
+    case_sensitive: bool,
+    buf: Vec<char>,
+}
+
+impl LineMatcher {
+    // threshold is the minimum fuzzy score per query character
+    pub fn new(query: &str, algorithm: Match, case_sensitive: bool, threshold: u32) -> Self {
+        let compiled = match algorithm {
+            Match::Fuzzy => Compiled::Fuzzy {
+                matcher: Matcher::new(Config::DEFAULT.match_paths()),
+                pattern: Pattern::parse(
+                    query,
+                    if case_sensitive {
+                        CaseMatching::Respect
+                    } else {
+                        CaseMatching::Ignore
+                    },
+                    Normalization::Smart,
+                ),
+                threshold: threshold * query.chars().count() as u32,
+            },
+            Match::Exact => Compiled::Exact(query.chars().collect()),
+            Match::Prefix => Compiled::Prefix(query.chars().collect()),
+            Match::Regex => Compiled::Regex(
+                RegexBuilder::new(query)
+                    .case_insensitive(!case_sensitive)
+                    .build()
+                    .ok(),
+            ),
+            Match::Tokens => Compiled::Tokens(
+                query
+                    .split_whitespace()
+                    .map(|t| t.chars().collect())
+                    .collect(),
+            ),
+        };
+
+        Self {
+            compiled,
+            case_sensitive,
+            buf: Vec::new(),
+        }
+    }
+
+    // the score and the positions of the matched characters
+    pub fn score(&mut self, text: &str) -> Option<(u32, Vec<u32>)> {
+        let cs = self.case_sensitive;
+
+        match &mut self.compiled {
+            Compiled::Fuzzy {
+                matcher,
+                pattern,
+                threshold,
+            } => {
+                let mut indices = Vec::new();
+                let score =
+                    pattern.indices(Utf32Str::new(text, &mut self.buf), matcher, &mut indices)?;
+
+                (score >= *threshold).then_some((score, indices))
+            }
+            Compiled::Exact(needle) => {
+                let chars: Vec<char> = text.chars().collect();
+                let pos = find(&chars, needle, cs)?;
+
+                Some((position_score(pos), span(pos, needle.len())))
+            }
+            Compiled::Prefix(needle) => {
+                let chars: Vec<char> = text.chars().collect();
+
+                (find(&chars[..needle.len().min(chars.len())], needle, cs) == Some(0))
+                    .then(|| (position_score(0), span(0, needle.len())))
+            }
+            Compiled::Regex(regex) => {
+                let m = regex.as_ref()?.find(text)?;
+                let start = text[..m.start()].chars().count();
+
+                Some((
+                    position_score(start),
+                    span(start, m.as_str().chars().count()),
+                ))
+            }
+            Compiled::Tokens(tokens) => {
+                let chars: Vec<char> = text.chars().collect();
+                let mut first = usize::MAX;
+                let mut indices = Vec::new();
+
+                for token in tokens.iter() {
+                    let pos = find(&chars, token, cs)?;
+                    first = first.min(pos);
+                    indices.extend(span(pos, token.len()));
+                }
+
+                Some((position_score(first.min(chars.len())), indices))
+            }
+        }
+    }
+}
+
+fn find(haystack: &[char], needle: &[char], case_sensitive: bool) -> Option<usize> {
+    let eq =
+        |a: &char, b: &char| a == b || (!case_sensitive && a.to_lowercase().eq(b.to_lowercase()));
+
+    if needle.is_empty() {
+        return Some(0);
+    }
+
+    haystack
+        .windows(needle.len())
+        .position(|w| w.iter().zip(needle).all(|(a, b)| eq(a, b)))
+}
+
+fn position_score(pos: usize) -> u32 {
+    1000 - pos.min(999) as u32
+}
+
+fn span(start: usize, len: usize) -> Vec<u32> {
+    (start as u32..(start + len) as u32).collect()
+}
 
 #[derive(Debug)]
 pub struct Dmenu {
@@ -20,3 +295,192 @@ impl Provider for Dmenu {
         include_str!("../../resources/themes/default/item_dmenu.xml").to_string()
     }
 }
+
+// fzf's --with-nth syntax: 1-based, comma separated N, N..M, N.., ..M and .., negative from the end
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fields(Vec<(Option<i64>, Option<i64>)>);
+
+impl Fields {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let index = |s: &str| -> Result<Option<i64>, String> {
+            if s.is_empty() {
+                return Ok(None);
+            }
+
+            match s.parse::<i64>() {
+                Ok(0) | Err(_) => Err(format!("invalid field index: '{s}'")),
+                Ok(i) => Ok(Some(i)),
+            }
+        };
+
+        spec.split(',')
+            .map(str::trim)
+            .map(|part| match part.split_once("..") {
+                Some((from, to)) => Ok((index(from)?, index(to)?)),
+                None if part.is_empty() => Err("empty field index".to_string()),
+                None => index(part).map(|i| (i, i)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Fields)
+    }
+
+    pub fn select(&self, line: &str, delimiter: &str) -> String {
+        let fields: Vec<&str> = line.split(delimiter).collect();
+        let len = fields.len() as i64;
+
+        // 1-based and negative indices to 0-based ones
+        let resolve = |i: i64| if i < 0 { len + i } else { i - 1 };
+
+        self.0
+            .iter()
+            .flat_map(|(from, to)| {
+                let from = from.map(resolve).unwrap_or(0).max(0);
+                let to = to.map(resolve).unwrap_or(len - 1).min(len - 1);
+                from..=to
+            })
+            .map(|i| fields[i as usize])
+            .collect::<Vec<_>>()
+            .join(delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(
+        algorithm: Match,
+        case_sensitive: bool,
+        query: &str,
+        text: &str,
+    ) -> Option<(u32, Vec<u32>)> {
+        LineMatcher::new(query, algorithm, case_sensitive, 0).score(text)
+    }
+
+    #[test]
+    fn exact_and_prefix() {
+        assert_eq!(
+            scores(Match::Exact, false, "Fox", "firefox"),
+            Some((996, vec![4, 5, 6]))
+        );
+        assert_eq!(scores(Match::Exact, true, "Fox", "firefox"), None);
+        assert_eq!(
+            scores(Match::Prefix, false, "fire", "firefox"),
+            Some((1000, vec![0, 1, 2, 3]))
+        );
+        assert_eq!(scores(Match::Prefix, false, "fox", "firefox"), None);
+        assert_eq!(scores(Match::Prefix, false, "firefoxes", "firefox"), None);
+    }
+
+    #[test]
+    fn regex_and_tokens() {
+        assert_eq!(
+            scores(Match::Regex, false, "f.x$", "Firefox"),
+            Some((996, vec![4, 5, 6]))
+        );
+        assert_eq!(scores(Match::Regex, false, "(", "firefox"), None);
+        assert_eq!(
+            scores(Match::Tokens, false, "fox fire", "firefox"),
+            Some((1000, vec![4, 5, 6, 0, 1, 2, 3]))
+        );
+        assert_eq!(scores(Match::Tokens, false, "fox chrome", "firefox"), None);
+    }
+
+    #[test]
+    fn fuzzy_threshold() {
+        assert!(
+            LineMatcher::new("ff", Match::Fuzzy, false, 0)
+                .score("firefox")
+                .is_some()
+        );
+        assert!(
+            LineMatcher::new("ff", Match::Fuzzy, false, 1000)
+                .score("firefox")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn plain_line() {
+        let item = parse_line("firefox", false).unwrap().item;
+
+        assert_eq!(item.text, "firefox");
+        assert_eq!(item.identifier, "firefox");
+        assert!(item.icon.is_empty());
+    }
+
+    #[test]
+    fn rofi_line() {
+        let line = parse_line(
+            "ff\0icon\x1ffirefox\x1fdisplay\x1fFirefox\x1fsubtext\x1fWeb Browser\x1furgent\x1ftrue\x1fmeta\x1fweb\x1finfo\x1fmozilla",
+            false,
+        )
+        .unwrap();
+        let item = &line.item;
+
+        assert_eq!(item.identifier, "ff");
+        assert_eq!(item.text, "Firefox");
+        assert_eq!(item.icon, "firefox");
+        assert_eq!(item.subtext, "Web Browser");
+        assert_eq!(item.state, vec!["urgent"]);
+        assert_eq!(line.info, "mozilla");
+        assert_eq!(line.match_text(), "Firefox web");
+    }
+
+    #[test]
+    fn json_line() {
+        let item = parse_line(
+            r#"{"text": "ff", "icon": "firefox", "state": ["active"], "actions": ["open", "delete"]}"#,
+            true,
+        )
+        .unwrap()
+        .item;
+
+        assert_eq!(item.identifier, "ff");
+        assert_eq!(item.text, "ff");
+        assert_eq!(item.icon, "firefox");
+        assert_eq!(item.state, vec!["active"]);
+        assert_eq!(item.actions, vec!["open", "delete"]);
+
+        assert!(parse_line("ff", true).is_err());
+    }
+
+    #[test]
+    fn line_index() {
+        let mut item = Item::new();
+        item.score = BASE_SCORE - 3;
+
+        assert_eq!(index(&item), 3);
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(
+            Fields::parse("1,3..,..2,-1,2..-2").unwrap(),
+            Fields(vec![
+                (Some(1), Some(1)),
+                (Some(3), None),
+                (None, Some(2)),
+                (Some(-1), Some(-1)),
+                (Some(2), Some(-2)),
+            ])
+        );
+
+        assert!(Fields::parse("0").is_err());
+        assert!(Fields::parse("a").is_err());
+        assert!(Fields::parse("1,").is_err());
+    }
+
+    #[test]
+    fn select_fields() {
+        let line = "id\tname\tcomment";
+        let select = |spec: &str| Fields::parse(spec).unwrap().select(line, "\t");
+
+        assert_eq!(select("2"), "name");
+        assert_eq!(select("2.."), "name\tcomment");
+        assert_eq!(select("-1,1"), "comment\tid");
+        assert_eq!(select(".."), line);
+        assert_eq!(select("4"), "");
+        assert_eq!(select("3..1"), "");
+    }
+}
@@ -13,8 +13,8 @@ use crate::{
     protos::generated_proto::query::query_response::Item,
     providers::{
         archlinuxpkgs::ArchLinuxPkgs, calc::Calc, clipboard::Clipboard,
-        default_provider::DefaultProvider, dmenu::Dmenu, files::Files, providerlist::Providerlist,
-        symbols::Symbols, todo::Todo, unicode::Unicode,
+        default_provider::DefaultProvider, desktopapplications::DesktopApplications, dmenu::Dmenu,
+        files::Files, providerlist::Providerlist, symbols::Symbols, todo::Todo, unicode::Unicode,
     },
 };
 
@@ -22,6 +22,7 @@ pub mod archlinuxpkgs;
 pub mod calc;
 pub mod clipboard;
 pub mod default_provider;
+pub mod desktopapplications;
 pub mod dmenu;
 pub mod files;
 pub mod providerlist;
@@ -32,6 +33,13 @@ pub mod unicode;
 pub trait Provider: Sync + Send + Debug {
     fn get_name(&self) -> &str;
 
+    // only built-in providers answer queries without elephant
+    fn query(&self, _query: &str, _exact: bool) -> Option<Vec<Item>> {
+        None
+    }
+
+    fn activate(&self, _item: &Item, _action: &str) {}
+
     fn get_actions(&self) -> Vec<Action> {
         get_config()
             .providers
@@ -212,6 +220,14 @@ pub fn setup_providers(elephant: bool) {
         }
     };
 
+    // without elephant there is at least something to launch
+    if !elephant {
+        providers.insert(
+            "desktopapplications".to_string(),
+            Box::new(DesktopApplications::new()),
+        );
+    }
+
     provider_list.into_iter().for_each(|p| {
         match p.as_str() {
             "calc" => providers.insert("calc".to_string(), Box::new(Calc::new())),
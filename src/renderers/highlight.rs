@@ -0,0 +1,183 @@
+use crate::protos::generated_proto::query::query_response::Item;
+use crate::protos::generated_proto::query::query_response::item::FuzzyInfo;
+use crate::ui::window::with_window;
+use gtk4::pango::{AttrColor, AttrInt, AttrList, Weight};
+use gtk4::prelude::WidgetExt;
+use gtk4::{Builder, Label};
+
+// nucleo's indices are neither sorted nor deduplicated
+pub fn fuzzy_info(field: &str, mut indices: Vec<u32>) -> FuzzyInfo {
+    indices.sort_unstable();
+    indices.dedup();
+
+    let mut info = FuzzyInfo::new();
+    info.field = field.to_string();
+    info.positions = indices.into_iter().map(|i| i as i32).collect();
+    info.start = info.positions.first().copied().unwrap_or_default();
+    info
+}
+
+// matched characters of the text or subtext, styled by the theme's `.item-text .match`
+pub fn highlight_matches(b: &Builder, item: &Item) {
+    let Some(info) = item.fuzzyinfo.as_ref() else {
+        return;
+    };
+
+    if info.positions.is_empty() {
+        return;
+    }
+
+    let (label, value) = match info.field.as_str() {
+        "" | "text" => (b.object::<Label>("ItemText"), &item.text),
+        "subtext" => (b.object::<Label>("ItemSubtext"), &item.subtext),
+        _ => return,
+    };
+
+    let Some(label) = label else {
+        return;
+    };
+
+    let ranges = match_ranges(value, &label.text(), &info.positions);
+
+    if ranges.is_empty() {
+        return;
+    }
+
+    let style = match_style();
+
+    let attrs = label
+        .attributes()
+        .and_then(|a| a.copy())
+        .unwrap_or_else(AttrList::new);
+
+    for (start, end) in ranges {
+        for mut attr in style.attributes() {
+            attr.set_start_index(start);
+            attr.set_end_index(end);
+            attrs.insert(attr);
+        }
+    }
+
+    label.set_attributes(Some(&attrs));
+    label.add_css_class("has-matches");
+}
+
+// read off the layout's hidden 'Match' label, which has the match class inside
+// an item-text box. only what the theme changes compared to the box is used,
+// so the rest follows the item, f.e. when it's selected. bold without the label.
+fn match_style() -> AttrList {
+    let attrs = AttrList::new();
+
+    let Some(probe) = with_window(|w| w.builder.object::<Label>("Match")) else {
+        attrs.insert(AttrInt::new_weight(Weight::Bold));
+        return attrs;
+    };
+
+    if let Some(a) = probe.attributes() {
+        a.attributes().into_iter().for_each(|a| attrs.insert(a));
+    }
+
+    let Some(parent) = probe.parent() else {
+        return attrs;
+    };
+
+    let color = probe.color();
+
+    if color != parent.color() {
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 65535.0) as u16;
+
+        attrs.insert(AttrColor::new_foreground(
+            channel(color.red()),
+            channel(color.green()),
+            channel(color.blue()),
+        ));
+        attrs.insert(AttrInt::new_foreground_alpha(channel(color.alpha())));
+    }
+
+    if let (Some(font), Some(base)) = (
+        probe.pango_context().font_description(),
+        parent.pango_context().font_description(),
+    ) {
+        if font.weight() != base.weight() {
+            attrs.insert(AttrInt::new_weight(font.weight()));
+        }
+
+        if font.style() != base.style() {
+            attrs.insert(AttrInt::new_style(font.style()));
+        }
+    }
+
+    attrs
+}
+
+// positions are characters of the original value, transformers might show
+// only part of it, f.e. a path without $HOME. returns byte ranges of `shown`.
+fn match_ranges(value: &str, shown: &str, positions: &[i32]) -> Vec<(u32, u32)> {
+    let Some(offset) = value.find(shown).filter(|_| !shown.is_empty()) else {
+        return Vec::new();
+    };
+
+    let skipped = value[..offset].chars().count() as i32;
+    let chars: Vec<(usize, char)> = shown.char_indices().collect();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+
+    for p in positions {
+        let Some(&(byte, c)) = usize::try_from(p - skipped).ok().and_then(|i| chars.get(i)) else {
+            continue;
+        };
+
+        let (start, end) = (byte as u32, (byte + c.len_utf8()) as u32);
+
+        match ranges.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            match_ranges("firefox", "firefox", &[0, 1, 4]),
+            vec![(0, 2), (4, 5)]
+        );
+        assert_eq!(match_ranges("firefox", "firefox", &[]), vec![]);
+    }
+
+    #[test]
+    fn ranges_of_transformed_text() {
+        // $HOME stripped by the files provider
+        assert_eq!(
+            match_ranges("/home/me/notes.md", "/notes.md", &[0, 9, 10]),
+            vec![(1, 3)]
+        );
+
+        // trimmed clipboard entry
+        assert_eq!(
+            match_ranges("  abc  ", "abc", &[2, 4]),
+            vec![(0, 1), (2, 3)]
+        );
+
+        assert_eq!(match_ranges("firefox", "chromium", &[0]), vec![]);
+    }
+
+    #[test]
+    fn ranges_are_bytes() {
+        assert_eq!(match_ranges("äöü", "äöü", &[1, 2]), vec![(2, 6)]);
+    }
+
+    #[test]
+    fn info_from_indices() {
+        let info = fuzzy_info("text", vec![4, 1, 4, 2]);
+
+        assert_eq!(info.positions, vec![1, 2, 4]);
+        assert_eq!(info.start, 1);
+        assert_eq!(info.field, "text");
+    }
+}
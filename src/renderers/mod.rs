@@ -1,3 +1,6 @@
+pub mod highlight;
+
+use crate::QueryResponseObject;
 use crate::config::get_config;
 use crate::protos::generated_proto::query::query_response::Item;
 use crate::providers::PROVIDERS;
@@ -7,8 +10,9 @@ use crate::ui::window::{quit, with_window};
 use gtk4::gdk::ContentProvider;
 use gtk4::gio::File;
 use gtk4::gio::prelude::FileExt;
+use gtk4::glib::object::{CastNone, ObjectExt};
 use gtk4::prelude::{ListItemExt, WidgetExt};
-use gtk4::{Box, Builder, DragSource, Label, ListItem, glib};
+use gtk4::{Box, Builder, DragSource, Image, Label, ListHeader, ListItem, glib};
 use std::path::Path;
 
 pub fn create_item(list_item: &ListItem, item: &Item, theme: &Theme) {
@@ -49,6 +53,14 @@ pub fn create_item(list_item: &ListItem, item: &Item, theme: &Theme) {
         .filter(|i| !i.is_empty())
         .for_each(|i| itembox.add_css_class(i));
 
+    if list_item
+        .item()
+        .and_downcast_ref::<QueryResponseObject>()
+        .is_some_and(QueryResponseObject::is_marked)
+    {
+        itembox.add_css_class("marked");
+    }
+
     if get_dmenu_current() != 0 && get_dmenu_current() as u32 == list_item.position() + 1 {
         itembox.add_css_class("current");
     }
@@ -69,6 +81,8 @@ pub fn create_item(list_item: &ListItem, item: &Item, theme: &Theme) {
         p.subtext_transformer(item, &text);
     }
 
+    highlight::highlight_matches(&b, item);
+
     p.image_transformer(&b, list_item, item);
 
     if let Some(text) = b.object::<Label>("QuickActivation") {
@@ -89,6 +103,43 @@ pub fn create_item(list_item: &ListItem, item: &Item, theme: &Theme) {
     }
 }
 
+pub fn create_section_header(header: &ListHeader, provider: &str, theme: &Theme) {
+    let b = Builder::new();
+    let _ = b.add_from_string(&theme.section);
+
+    let Some(sectionbox) = b.object::<Box>("SectionBox") else {
+        set_error("Theme: missing 'SectionBox' object".to_string());
+        return;
+    };
+
+    sectionbox.add_css_class(&provider.replace("menus:", "menus-"));
+
+    let (name, icon) = get_config().sections.header(provider);
+
+    if let Some(label) = b.object::<Label>("SectionName") {
+        label.set_label(&name);
+    }
+
+    if let Some(image) = b.object::<Image>("SectionIcon") {
+        match icon {
+            Some(icon) if Path::new(&icon).is_absolute() => image.set_from_file(Some(&icon)),
+            Some(icon) => image.set_icon_name(Some(&icon)),
+            None => image.set_visible(false),
+        }
+    }
+
+    // the section grows and shrinks while results stream in
+    if let Some(count) = b.object::<Label>("SectionCount") {
+        header
+            .bind_property("n-items", &count, "label")
+            .transform_to(|_, n: u32| Some(n.to_string()))
+            .sync_create()
+            .build();
+    }
+
+    header.set_child(Some(&sectionbox));
+}
+
 pub fn create_drag_source(text: &str) -> DragSource {
     let drag_source = DragSource::new();
     let text = text.to_string();
@@ -2,19 +2,61 @@ use std::collections::HashSet;
 use std::sync::{OnceLock, RwLock};
 
 use crate::keybinds::AfterAction;
+use crate::providers::dmenu::{Fields, Format, Match};
 
 static STATE: OnceLock<RwLock<AppState>> = OnceLock::new();
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    // the last attempt failed, another one is scheduled
+    Failed,
+    // elephant speaks a protocol we don't, waiting for it to be restarted
+    Incompatible,
+}
+
+impl ConnectionState {
+    pub const ALL: [ConnectionState; 5] = [
+        ConnectionState::Disconnected,
+        ConnectionState::Connecting,
+        ConnectionState::Connected,
+        ConnectionState::Failed,
+        ConnectionState::Incompatible,
+    ];
+
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "elephant-disconnected",
+            ConnectionState::Connecting => "elephant-connecting",
+            ConnectionState::Connected => "elephant-connected",
+            ConnectionState::Failed => "elephant-failed",
+            ConnectionState::Incompatible => "elephant-incompatible",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct AppState {
     error: String,
     async_after: Option<AfterAction>,
     hide_qa: bool,
     has_elephant: bool,
-    is_connected: bool,
-    is_connecting: bool,
+    connection: ConnectionState,
     dmenu_keep_open: bool,
     dmenu_exit_after: bool,
+    dmenu_multi: bool,
+    dmenu_json: bool,
+    dmenu_delimiter: String,
+    dmenu_preview: String,
+    dmenu_match: Match,
+    dmenu_case_sensitive: bool,
+    dmenu_sort: bool,
+    dmenu_with_nth: Option<Fields>,
+    dmenu_accept_nth: Option<Fields>,
+    dmenu_format: Format,
     dmenu_current: i64,
     initial_height: Option<i32>,
     initial_width: Option<i32>,
@@ -35,10 +77,12 @@ pub struct AppState {
     provider: String,
     prefix_provider: String,
     theme: String,
+    socket: String,
     is_service: bool,
     no_search: bool,
     no_hints: bool,
     input_only: bool,
+    password: bool,
     is_dmenu: bool,
     is_param_close: bool,
     current_prefix: String,
@@ -53,6 +97,11 @@ pub fn init_app_state() {
         .expect("can't init appstate");
 }
 
+#[cfg(test)]
+pub fn init_test_state() {
+    STATE.get_or_init(|| RwLock::new(AppState::default()));
+}
+
 pub fn get_theme() -> String {
     STATE.get().unwrap().read().unwrap().theme.clone()
 }
@@ -61,6 +110,14 @@ pub fn set_theme(val: String) {
     STATE.get().unwrap().write().unwrap().theme = val
 }
 
+pub fn get_socket() -> String {
+    STATE.get().unwrap().read().unwrap().socket.clone()
+}
+
+pub fn set_socket(val: String) {
+    STATE.get().unwrap().write().unwrap().socket = val
+}
+
 pub fn get_async_after() -> Option<AfterAction> {
     STATE.get().unwrap().read().unwrap().async_after.clone()
 }
@@ -159,20 +216,21 @@ pub fn set_has_elephant(val: bool) {
     STATE.get().unwrap().write().unwrap().has_elephant = val
 }
 
-pub fn is_connected() -> bool {
-    STATE.get().unwrap().read().unwrap().is_connected
+pub fn get_connection_state() -> ConnectionState {
+    STATE.get().unwrap().read().unwrap().connection
 }
 
-pub fn set_is_connected(val: bool) {
-    STATE.get().unwrap().write().unwrap().is_connected = val
+pub fn set_connection_state(val: ConnectionState) {
+    STATE.get().unwrap().write().unwrap().connection = val
 }
 
-pub fn is_connecting() -> bool {
-    STATE.get().unwrap().read().unwrap().is_connecting
+pub fn is_connected() -> bool {
+    get_connection_state() == ConnectionState::Connected
 }
 
-pub fn set_is_connecting(val: bool) {
-    STATE.get().unwrap().write().unwrap().is_connecting = val
+// without elephant the built-in providers answer queries
+pub fn can_query() -> bool {
+    !has_elephant() || is_connected()
 }
 
 pub fn is_input_only() -> bool {
@@ -183,6 +241,14 @@ pub fn set_input_only(val: bool) {
     STATE.get().unwrap().write().unwrap().input_only = val
 }
 
+pub fn is_password() -> bool {
+    STATE.get().unwrap().read().unwrap().password
+}
+
+pub fn set_password(val: bool) {
+    STATE.get().unwrap().write().unwrap().password = val
+}
+
 pub fn is_param_close() -> bool {
     STATE.get().unwrap().read().unwrap().is_param_close
 }
@@ -207,6 +273,92 @@ pub fn set_dmenu_exit_after(val: bool) {
     STATE.get().unwrap().write().unwrap().dmenu_exit_after = val
 }
 
+pub fn is_dmenu_multi() -> bool {
+    STATE.get().unwrap().read().unwrap().dmenu_multi
+}
+
+pub fn set_dmenu_multi(val: bool) {
+    STATE.get().unwrap().write().unwrap().dmenu_multi = val
+}
+
+pub fn is_dmenu_json() -> bool {
+    STATE.get().unwrap().read().unwrap().dmenu_json
+}
+
+pub fn set_dmenu_json(val: bool) {
+    STATE.get().unwrap().write().unwrap().dmenu_json = val
+}
+
+pub fn get_dmenu_delimiter() -> String {
+    STATE.get().unwrap().read().unwrap().dmenu_delimiter.clone()
+}
+
+pub fn set_dmenu_delimiter(val: String) {
+    STATE.get().unwrap().write().unwrap().dmenu_delimiter = val
+}
+
+pub fn get_dmenu_match() -> Match {
+    STATE.get().unwrap().read().unwrap().dmenu_match
+}
+
+pub fn set_dmenu_match(val: Match) {
+    STATE.get().unwrap().write().unwrap().dmenu_match = val
+}
+
+pub fn is_dmenu_case_sensitive() -> bool {
+    STATE.get().unwrap().read().unwrap().dmenu_case_sensitive
+}
+
+pub fn set_dmenu_case_sensitive(val: bool) {
+    STATE.get().unwrap().write().unwrap().dmenu_case_sensitive = val
+}
+
+pub fn is_dmenu_sort() -> bool {
+    STATE.get().unwrap().read().unwrap().dmenu_sort
+}
+
+pub fn set_dmenu_sort(val: bool) {
+    STATE.get().unwrap().write().unwrap().dmenu_sort = val
+}
+
+pub fn get_dmenu_preview() -> String {
+    STATE.get().unwrap().read().unwrap().dmenu_preview.clone()
+}
+
+pub fn set_dmenu_preview(val: String) {
+    STATE.get().unwrap().write().unwrap().dmenu_preview = val
+}
+
+pub fn get_dmenu_with_nth() -> Option<Fields> {
+    STATE.get().unwrap().read().unwrap().dmenu_with_nth.clone()
+}
+
+pub fn set_dmenu_with_nth(val: Option<Fields>) {
+    STATE.get().unwrap().write().unwrap().dmenu_with_nth = val
+}
+
+pub fn get_dmenu_accept_nth() -> Option<Fields> {
+    STATE
+        .get()
+        .unwrap()
+        .read()
+        .unwrap()
+        .dmenu_accept_nth
+        .clone()
+}
+
+pub fn set_dmenu_accept_nth(val: Option<Fields>) {
+    STATE.get().unwrap().write().unwrap().dmenu_accept_nth = val
+}
+
+pub fn get_dmenu_format() -> Format {
+    STATE.get().unwrap().read().unwrap().dmenu_format
+}
+
+pub fn set_dmenu_format(val: Format) {
+    STATE.get().unwrap().write().unwrap().dmenu_format = val
+}
+
 pub fn is_dmenu() -> bool {
     STATE.get().unwrap().read().unwrap().is_dmenu
 }
@@ -223,10 +375,6 @@ pub fn set_hide_qa(val: bool) {
     STATE.get().unwrap().write().unwrap().hide_qa = val
 }
 
-pub fn query() -> String {
-    STATE.get().unwrap().read().unwrap().query.clone()
-}
-
 pub fn set_query(val: &str) {
     STATE.get().unwrap().write().unwrap().query = val.to_string()
 }
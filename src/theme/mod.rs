@@ -20,6 +20,7 @@ pub struct Theme {
     pub layout: String,
     pub keybind: String,
     pub preview: String,
+    pub section: String,
     pub css: Option<gio::File>,
     pub items: HashMap<String, String>,
 }
@@ -30,6 +31,7 @@ impl Theme {
             layout: include_str!("../../resources/themes/default/layout.xml").to_string(),
             keybind: include_str!("../../resources/themes/default/keybind.xml").to_string(),
             preview: include_str!("../../resources/themes/default/preview.xml").to_string(),
+            section: include_str!("../../resources/themes/default/section.xml").to_string(),
             css: None,
             items: HashMap::new(),
         };
@@ -60,6 +62,7 @@ pub fn setup_themes(elephant: bool, theme: String, is_service: bool) {
         "keybind.xml".to_string(),
         "style.css".to_string(),
         "preview.xml".to_string(),
+        "section.xml".to_string(),
     ];
 
     let combined = if elephant {
@@ -169,6 +172,11 @@ fn setup_theme_from_path(path: PathBuf, files: &Vec<String>) -> Option<Theme> {
                     theme.preview = s;
                 }
             }
+            "section.xml" => {
+                if let Some(s) = read_file(file) {
+                    theme.section = s;
+                }
+            }
             name if name.ends_with(".xml") && name.starts_with("item_") => {
                 if let Some(s) = read_file(file) {
                     let key = name
@@ -0,0 +1,435 @@
+use crate::config::get_config;
+use crate::protos::QueryResponseObject;
+use crate::protos::generated_proto::query::QueryResponse;
+use crate::protos::generated_proto::query::query_response::Item;
+use crate::providers::dmenu::{self, Line, LineMatcher, Match};
+use crate::renderers::highlight::fuzzy_info;
+use crate::state::{get_dmenu_match, is_dmenu_case_sensitive, is_dmenu_sort};
+use crate::ui::window::{set_keybind_hint, with_window};
+use gtk4::glib;
+use gtk4::prelude::*;
+use nucleo::pattern::{CaseMatching, Normalization};
+use nucleo::{Config, Nucleo};
+use protobuf::MessageField;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use std::time::Duration;
+
+// matches put into the list at once, more follow when scrolling to its end
+const WINDOW: u32 = 200;
+
+static TICK_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static LINES: RefCell<Option<DmenuLines>> = const { RefCell::new(None) };
+}
+
+struct DmenuLines {
+    // fuzzy only, the other modes are scored on the main thread
+    nucleo: Option<Nucleo<u32>>,
+    lines: Vec<Line>,
+    // (line index, score) in display order
+    matches: Vec<(u32, u32)>,
+    visible: Vec<u32>,
+    marked: HashSet<i64>,
+    query: String,
+    query_changed: bool,
+    matcher: LineMatcher,
+    algorithm: Match,
+    case_sensitive: bool,
+    sort: bool,
+    scored: usize,
+    shown: u32,
+}
+
+impl DmenuLines {
+    fn new() -> Self {
+        let algorithm = get_dmenu_match();
+        let case_sensitive = is_dmenu_case_sensitive();
+
+        let nucleo = (algorithm == Match::Fuzzy).then(|| {
+            Nucleo::new(
+                Config::DEFAULT.match_paths(),
+                Arc::new(schedule_tick),
+                None,
+                1,
+            )
+        });
+
+        Self {
+            nucleo,
+            lines: Vec::new(),
+            matches: Vec::new(),
+            visible: Vec::new(),
+            marked: HashSet::new(),
+            query: String::new(),
+            query_changed: false,
+            matcher: matcher("", algorithm, case_sensitive),
+            algorithm,
+            case_sensitive,
+            sort: is_dmenu_sort(),
+            scored: 0,
+            shown: WINDOW,
+        }
+    }
+
+    fn push(&mut self, lines: Vec<Line>) {
+        if let Some(nucleo) = &self.nucleo {
+            let injector = nucleo.injector();
+
+            for (i, line) in lines.iter().enumerate() {
+                let idx = (self.lines.len() + i) as u32;
+                injector.push(idx, |_, columns| columns[0] = line.match_text().into());
+            }
+        }
+
+        self.lines.extend(lines);
+    }
+
+    fn set_query(&mut self, query: &str) {
+        if let Some(nucleo) = &mut self.nucleo {
+            let append = query.starts_with(&self.query) && !self.query.ends_with('\\');
+
+            nucleo.pattern.reparse(
+                0,
+                query,
+                if self.case_sensitive {
+                    CaseMatching::Respect
+                } else {
+                    CaseMatching::Ignore
+                },
+                Normalization::Smart,
+                append,
+            );
+        } else {
+            self.matches.clear();
+            self.scored = 0;
+        }
+
+        self.matcher = matcher(query, self.algorithm, self.case_sensitive);
+        self.query = query.to_string();
+        self.query_changed = true;
+        self.shown = WINDOW;
+    }
+
+    // true if the matches changed
+    fn update(&mut self) -> bool {
+        if let Some(nucleo) = &mut self.nucleo {
+            let status = nucleo.tick(0);
+
+            if status.running {
+                glib::timeout_add_once(Duration::from_millis(10), schedule_tick);
+            }
+
+            if !status.changed {
+                return false;
+            }
+
+            let snapshot = nucleo.snapshot();
+            let line = |n: u32| *snapshot.get_matched_item(n).unwrap().data;
+
+            // best first, so the lines above dmenu.fuzzy_threshold are a prefix
+            let (mut low, mut high) = (0, snapshot.matched_item_count());
+
+            if self.query.is_empty() {
+                low = high;
+            }
+
+            while low < high {
+                let mid = low + (high - low) / 2;
+
+                if self
+                    .matcher
+                    .score(&self.lines[line(mid) as usize].match_text())
+                    .is_some()
+                {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+
+            // nucleo doesn't hand out its scores, the order is all that's needed
+            self.matches = snapshot
+                .matched_items(..low)
+                .map(|item| (*item.data, 0))
+                .collect();
+
+            if !self.sort {
+                self.matches.sort_unstable();
+            }
+
+            return true;
+        }
+
+        if self.scored == self.lines.len() {
+            return false;
+        }
+
+        let new = self.scored..self.lines.len();
+        self.scored = self.lines.len();
+
+        if self.query.is_empty() {
+            self.matches.extend(new.map(|i| (i as u32, 0)));
+            return true;
+        }
+
+        let mut scored: Vec<(u32, u32)> = new
+            .filter_map(|i| {
+                let (score, _) = self.matcher.score(&self.lines[i].match_text())?;
+                Some((i as u32, score))
+            })
+            .collect();
+
+        if self.sort {
+            scored.sort_by(rank);
+            self.matches = merge(mem::take(&mut self.matches), scored);
+        } else {
+            self.matches.extend(scored);
+        }
+
+        true
+    }
+
+    // None if the list already shows these
+    fn shown_items(&mut self, force: bool) -> Option<Vec<QueryResponseObject>> {
+        let visible: Vec<u32> = self
+            .matches
+            .iter()
+            .take(self.shown as usize)
+            .map(|&(idx, _)| idx)
+            .collect();
+
+        if !force && visible == self.visible {
+            return None;
+        }
+
+        let objs = visible.iter().map(|&idx| self.object(idx)).collect();
+        self.visible = visible;
+
+        Some(objs)
+    }
+
+    fn object(&mut self, idx: u32) -> QueryResponseObject {
+        let mut item = self.lines[idx as usize].item.clone();
+
+        if !self.query.is_empty()
+            && let Some((_, indices)) = self.matcher.score(&item.text)
+        {
+            item.fuzzyinfo = MessageField::some(fuzzy_info("text", indices));
+        }
+
+        let marked = self.marked.contains(&dmenu::index(&item));
+
+        let mut response = QueryResponse::new();
+        response.item = MessageField::some(item);
+
+        let obj = QueryResponseObject::new(response);
+        obj.set_marked(marked);
+        obj
+    }
+}
+
+fn matcher(query: &str, algorithm: Match, case_sensitive: bool) -> LineMatcher {
+    LineMatcher::new(
+        query,
+        algorithm,
+        case_sensitive,
+        get_config().dmenu.fuzzy_threshold,
+    )
+}
+
+// higher score first, input order between equal ones
+fn rank(a: &(u32, u32), b: &(u32, u32)) -> Ordering {
+    b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))
+}
+
+fn merge(a: Vec<(u32, u32)>, b: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        if rank(x, y) == Ordering::Greater {
+            merged.extend(b.next());
+        } else {
+            merged.extend(a.next());
+        }
+    }
+
+    merged.extend(a);
+    merged.extend(b);
+    merged
+}
+
+fn with_lines<R>(f: impl FnOnce(&mut DmenuLines) -> R) -> Option<R> {
+    LINES.with(|lines| lines.borrow_mut().as_mut().map(f))
+}
+
+// called from nucleo's threads too
+fn schedule_tick() {
+    if !TICK_SCHEDULED.swap(true, atomic::Ordering::SeqCst) {
+        glib::idle_add_once(|| {
+            TICK_SCHEDULED.store(false, atomic::Ordering::SeqCst);
+            tick();
+        });
+    }
+}
+
+fn tick() {
+    let changed = with_lines(|lines| lines.update().then(|| mem::take(&mut lines.query_changed)));
+
+    // a new query starts at the top, new lines keep the selection
+    if let Some(Some(query_changed)) = changed {
+        refresh(query_changed, !query_changed);
+    }
+}
+
+// the store is replaced outside of the borrow, its signals might end up here again
+fn refresh(force: bool, keep_selection: bool) {
+    let Some(Some(objs)) = with_lines(|lines| lines.shown_items(force)) else {
+        return;
+    };
+
+    let line = |o: &QueryResponseObject| o.response().item.as_ref().map(dmenu::index);
+
+    with_window(|w| {
+        let selected = w
+            .selection
+            .selected_item()
+            .and_downcast::<QueryResponseObject>()
+            .filter(|_| keep_selection)
+            .and_then(|o| line(&o));
+
+        w.items.splice(0, w.items.n_items(), &objs);
+
+        if let Some(selected) = selected
+            && let Some(pos) = objs.iter().position(|o| line(o) == Some(selected))
+        {
+            w.selection.set_selected(pos as u32);
+        }
+    });
+
+    set_keybind_hint();
+}
+
+pub fn reset() {
+    LINES.with(|lines| *lines.borrow_mut() = Some(DmenuLines::new()));
+    with_window(|w| w.items.remove_all());
+}
+
+pub fn clear() {
+    LINES.with(|lines| lines.borrow_mut().take());
+}
+
+pub fn push(new: Vec<Line>) {
+    with_lines(|lines| lines.push(new));
+    schedule_tick();
+}
+
+pub fn set_query(query: &str) {
+    with_lines(|lines| lines.set_query(query));
+    schedule_tick();
+}
+
+// appends instead of replacing, so the view doesn't jump
+pub fn show_more() {
+    let objs = with_lines(|lines| {
+        lines.shown += WINDOW;
+
+        let more: Vec<u32> = lines
+            .matches
+            .iter()
+            .take(lines.shown as usize)
+            .skip(lines.visible.len())
+            .map(|&(idx, _)| idx)
+            .collect();
+
+        lines.visible.extend(&more);
+        more.into_iter()
+            .map(|idx| lines.object(idx))
+            .collect::<Vec<_>>()
+    });
+
+    if let Some(objs) = objs
+        && !objs.is_empty()
+    {
+        with_window(|w| w.items.extend_from_slice(&objs));
+    }
+}
+
+pub fn set_marked(item: &Item, marked: bool) {
+    with_lines(|lines| {
+        if marked {
+            lines.marked.insert(dmenu::index(item));
+        } else {
+            lines.marked.remove(&dmenu::index(item));
+        }
+    });
+}
+
+// marks all matching lines, or unmarks them if they all are already
+pub fn mark_all() {
+    with_lines(|lines| {
+        let matching: Vec<i64> = lines
+            .matches
+            .iter()
+            .map(|&(idx, _)| dmenu::index(&lines.lines[idx as usize].item))
+            .collect();
+
+        if matching.iter().all(|i| lines.marked.contains(i)) {
+            matching.iter().for_each(|i| {
+                lines.marked.remove(i);
+            });
+        } else {
+            lines.marked.extend(matching);
+        }
+    });
+
+    refresh(true, true);
+}
+
+pub fn marked_items() -> Vec<Item> {
+    with_lines(|lines| {
+        lines
+            .lines
+            .iter()
+            .filter(|l| lines.marked.contains(&dmenu::index(&l.item)))
+            .map(|l| l.item.clone())
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+// lines are kept in input order, so by ascending index
+pub fn info(item: &Item) -> String {
+    with_lines(|lines| {
+        let index = dmenu::index(item);
+
+        lines
+            .lines
+            .binary_search_by_key(&index, |l| dmenu::index(&l.item))
+            .map(|i| lines.lines[i].info.clone())
+            .unwrap_or_default()
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_by_rank() {
+        let old = vec![(4, 900), (1, 500), (0, 100)];
+        let new = vec![(7, 900), (5, 500), (6, 300)];
+
+        assert_eq!(
+            merge(old, new),
+            vec![(4, 900), (7, 900), (1, 500), (5, 500), (6, 300), (0, 100)]
+        );
+    }
+}
@@ -1 +1,2 @@
+pub mod dmenu_list;
 pub mod window;
@@ -1,36 +1,41 @@
 use crate::{
     GLOBAL_DMENU_SENDER, QueryResponseObject,
     config::get_config,
-    data::{activate, clipboard_disable_images_only, input_changed},
+    data::{activate, clipboard_disable_images_only, dmenu_result, input_changed},
     keybinds::{
-        ACTION_CLOSE, ACTION_QUICK_ACTIVATE, ACTION_RESUME_LAST_QUERY, ACTION_SELECT_NEXT,
-        ACTION_SELECT_PREVIOUS, ACTION_TOGGLE_EXACT, Action, AfterAction, get_bind,
-        get_provider_bind, get_provider_global_bind,
+        ACTION_CLOSE, ACTION_MARK_ALL, ACTION_QUICK_ACTIVATE, ACTION_RESUME_LAST_QUERY,
+        ACTION_SELECT_NEXT, ACTION_SELECT_PREVIOUS, ACTION_TOGGLE_EXACT, ACTION_TOGGLE_MARK,
+        Action, AfterAction, get_bind, get_dmenu_custom_bind, get_provider_bind,
+        get_provider_global_bind,
     },
     protos::generated_proto::query::QueryResponse,
     providers::{PROVIDERS, Provider},
-    renderers::create_item,
-    send_message,
+    renderers::{create_item, create_section_header},
+    send_message, send_message_with_status,
     state::{
-        get_current_prefix, get_error, get_initial_height, get_initial_max_height,
-        get_initial_max_width, get_initial_min_height, get_initial_min_width,
-        get_initial_placeholder, get_initial_width, get_last_query, get_prefix_provider,
-        get_provider, get_theme, is_connected, is_dmenu, is_dmenu_exit_after, is_dmenu_keep_open,
-        is_service, query, set_async_after, set_current_prefix, set_current_set, set_dmenu_current,
-        set_dmenu_exit_after, set_dmenu_keep_open, set_error, set_hide_qa, set_initial_height,
-        set_initial_max_height, set_initial_max_width, set_initial_min_height,
-        set_initial_min_width, set_initial_placeholder, set_initial_width, set_input_only,
-        set_is_dmenu, set_is_visible, set_last_query, set_no_hints, set_no_search, set_param_close,
-        set_parameter_height, set_parameter_max_height, set_parameter_max_width,
-        set_parameter_min_height, set_parameter_min_width, set_parameter_width, set_placeholder,
-        set_provider, set_query, set_theme,
+        can_query, get_current_prefix, get_dmenu_preview, get_error, get_initial_height,
+        get_initial_max_height, get_initial_max_width, get_initial_min_height,
+        get_initial_min_width, get_initial_placeholder, get_initial_width, get_last_query,
+        get_prefix_provider, get_provider, get_theme, is_dmenu, is_dmenu_exit_after,
+        is_dmenu_keep_open, is_dmenu_multi, is_password, is_service, set_async_after,
+        set_current_prefix, set_current_set, set_dmenu_accept_nth, set_dmenu_current,
+        set_dmenu_exit_after, set_dmenu_keep_open, set_dmenu_multi, set_dmenu_with_nth, set_error,
+        set_hide_qa, set_initial_height, set_initial_max_height, set_initial_max_width,
+        set_initial_min_height, set_initial_min_width, set_initial_placeholder, set_initial_width,
+        set_input_only, set_is_dmenu, set_is_visible, set_last_query, set_no_hints, set_no_search,
+        set_param_close, set_parameter_height, set_parameter_max_height, set_parameter_max_width,
+        set_parameter_min_height, set_parameter_min_width, set_parameter_width, set_password,
+        set_placeholder, set_provider, set_query, set_theme,
     },
+    stop_dmenu_input,
     theme::{Theme, setup_layer_shell, with_themes},
+    ui::dmenu_list,
 };
 use gtk4::{
-    Application, Builder, CustomFilter, Entry, EventControllerKey, EventControllerMotion,
-    FilterListModel, GestureClick, Label, PropagationPhase, ScrolledWindow, SignalListItemFactory,
-    SingleSelection, Window, prelude::BoxExt,
+    Application, Builder, CustomSorter, Entry, EventControllerKey, EventControllerMotion,
+    GestureClick, Label, ListHeader, ListView, PositionType, PropagationPhase, ScrolledWindow,
+    SignalListItemFactory, SingleSelection, SortListModel, SorterChange, Spinner, Window,
+    prelude::{BoxExt, SorterExt},
 };
 use gtk4::{Box, ListScrollFlags};
 use gtk4::{
@@ -43,11 +48,14 @@ use gtk4::{
     glib::object::{CastNone, ObjectExt},
 };
 use gtk4::{gdk, prelude::WidgetExt};
-use gtk4::{gio::ListStore, glib::object::Cast};
 use gtk4::{
     gio::prelude::{ApplicationExt, ListModelExt},
     prelude::GtkApplicationExt,
 };
+use gtk4::{
+    gio::{ListModel, ListStore},
+    glib::{self, object::Cast},
+};
 use gtk4::{
     glib::Object,
     prelude::{EntryExt, GtkWindowExt},
@@ -57,6 +65,7 @@ use std::{
     cell::{Cell, OnceCell, RefCell},
     collections::HashMap,
     env, process,
+    rc::Rc,
 };
 
 thread_local! {
@@ -85,9 +94,11 @@ pub struct WindowData {
     pub app: Application,
     pub window: Window,
     pub selection: SingleSelection,
-    pub list: GridView,
+    pub list: ResultList,
     pub input: Option<Entry>,
     pub items: ListStore,
+    // `items` grouped by provider, if sections are enabled
+    pub grouped: Option<SortListModel>,
     pub placeholder: Option<Label>,
     pub elephant_hint: Label,
     pub keybinds: Option<gtk4::Box>,
@@ -100,6 +111,96 @@ pub struct WindowData {
     pub error: gtk4::Label,
 }
 
+// sections need a GtkListView
+#[derive(Debug, Clone)]
+pub enum ResultList {
+    Grid(GridView),
+    List(ListView),
+}
+
+impl ResultList {
+    pub fn widget(&self) -> &gtk4::Widget {
+        match self {
+            ResultList::Grid(g) => g.upcast_ref(),
+            ResultList::List(l) => l.upcast_ref(),
+        }
+    }
+
+    pub fn scroll_to(&self, pos: u32, flags: ListScrollFlags) {
+        match self {
+            ResultList::Grid(g) => g.scroll_to(pos, flags, None),
+            ResultList::List(l) => l.scroll_to(pos, flags, None),
+        }
+    }
+
+    pub fn set_model(&self, model: &SingleSelection) {
+        match self {
+            ResultList::Grid(g) => g.set_model(Some(model)),
+            ResultList::List(l) => l.set_model(Some(model)),
+        }
+    }
+
+    pub fn set_factory(&self, factory: &SignalListItemFactory) {
+        match self {
+            ResultList::Grid(g) => g.set_factory(Some(factory)),
+            ResultList::List(l) => l.set_factory(Some(factory)),
+        }
+    }
+
+    pub fn set_single_click_activate(&self, val: bool) {
+        match self {
+            ResultList::Grid(g) => g.set_single_click_activate(val),
+            ResultList::List(l) => l.set_single_click_activate(val),
+        }
+    }
+
+    pub fn connect_activate<F: Fn() + 'static>(&self, f: F) {
+        match self {
+            ResultList::Grid(g) => {
+                g.connect_activate(move |_, _| f());
+            }
+            ResultList::List(l) => {
+                l.connect_activate(move |_, _| f());
+            }
+        }
+    }
+
+    pub fn can_target(&self) -> bool {
+        self.widget().can_target()
+    }
+
+    pub fn set_can_target(&self, val: bool) {
+        self.widget().set_can_target(val)
+    }
+
+    // sections need a ListView, a GridView is swapped for one with the same
+    // styling if it sits directly in the 'Scroll'.
+    fn into_list_view(self, scroll: &ScrolledWindow) -> Option<ListView> {
+        let grid = match self {
+            ResultList::List(l) => return Some(l),
+            ResultList::Grid(g) => g,
+        };
+
+        if grid.parent().as_ref() != Some(scroll.upcast_ref::<gtk4::Widget>()) {
+            return None;
+        }
+
+        let list = ListView::new(None::<SingleSelection>, None::<SignalListItemFactory>);
+        list.set_css_classes(
+            &grid
+                .css_classes()
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>(),
+        );
+        list.set_widget_name(&grid.widget_name());
+        list.set_can_focus(grid.can_focus());
+        scroll.set_child(Some(&list));
+
+        Some(list)
+    }
+}
+
 pub fn with_window<F, R>(f: F) -> R
 where
     F: FnOnce(&WindowData) -> R,
@@ -133,9 +234,13 @@ pub fn setup_theme_window(app: &Application, val: &Theme) -> Result<WindowData,
         None => return Err("missing 'Scroll' object".into()),
     };
 
-    let list: GridView = match builder.object("List") {
-        Some(w) => w,
-        None => return Err("missing 'List' object".into()),
+    let mut list = match (
+        builder.object::<GridView>("List"),
+        builder.object::<ListView>("List"),
+    ) {
+        (Some(g), _) => ResultList::Grid(g),
+        (_, Some(l)) => ResultList::List(l),
+        _ => return Err("missing 'List' object".into()),
     };
 
     let elephant_hint: Label = match builder.object("ElephantHint") {
@@ -162,27 +267,28 @@ pub fn setup_theme_window(app: &Application, val: &Theme) -> Result<WindowData,
     let placeholder: Option<Label> = builder.object("Placeholder");
     let keybinds: Option<gtk4::Box> = builder.object("Keybinds");
 
-    let filter = CustomFilter::new({
-        move |entry| {
-            let item = entry.downcast_ref::<QueryResponseObject>().unwrap();
-
-            let q = query();
-
-            if is_dmenu() && !q.is_empty() {
-                let f = 18 * q.len();
+    let items = ListStore::new::<QueryResponseObject>();
 
-                if item.dmenu_score() < f as u32 {
-                    return false;
-                }
+    let grouped = if get_config().sections.enabled {
+        match list.clone().into_list_view(&scroll) {
+            Some(l) => {
+                list = ResultList::List(l);
+                Some(group_by_provider(&items))
+            }
+            None => {
+                eprintln!("sections: 'List' has to be a GtkListView, results won't be grouped");
+                None
             }
-
-            true
         }
-    });
+    } else {
+        None
+    };
 
-    let items = ListStore::new::<QueryResponseObject>();
-    let filter_model = FilterListModel::new(Some(items.clone()), Some(filter.clone()));
-    let selection = SingleSelection::new(Some(filter_model.clone()));
+    let model: ListModel = match &grouped {
+        Some(grouped) => grouped.clone().upcast(),
+        None => items.clone().upcast(),
+    };
+    let selection = SingleSelection::new(Some(model));
     let search_container: Option<Box> = builder.object("SearchContainer");
     let preview_container: Option<Box> = builder.object("Preview");
     let border_gradient: Option<DrawingArea> = builder.object("BorderGradient");
@@ -207,6 +313,7 @@ pub fn setup_theme_window(app: &Application, val: &Theme) -> Result<WindowData,
         list,
         input,
         items,
+        grouped,
         placeholder,
         keybinds,
     };
@@ -440,7 +547,7 @@ fn setup_window_behavior(ui: &WindowData, app: &Application) {
         with_window(|w| {
             crate::handle_preview();
             w.list
-                .scroll_to(w.selection.selected(), ListScrollFlags::NONE, None);
+                .scroll_to(w.selection.selected(), ListScrollFlags::NONE);
 
             set_keybind_hint();
         });
@@ -448,7 +555,7 @@ fn setup_window_behavior(ui: &WindowData, app: &Application) {
 
     let app_copy = app.clone();
 
-    ui.list.connect_activate(move |_, _| {
+    ui.list.connect_activate(move || {
         activate_default(&app_copy);
     });
 
@@ -519,7 +626,7 @@ fn setup_keyboard_handling(ui: &WindowData) {
 
     controller.connect_key_pressed(move |_, k, _, m| {
         let handled = with_window(|w| {
-            if !is_connected() && !is_dmenu() {
+            if !can_query() && !is_dmenu() {
                 if let Some(action) = get_bind(k, m)
                     && action.action == ACTION_CLOSE
                 {
@@ -541,6 +648,8 @@ fn setup_keyboard_handling(ui: &WindowData) {
 
                 if text.is_empty() {
                     text = "CNCLD".to_string();
+                } else {
+                    text = dmenu_result(None, &text, "select");
                 }
 
                 if is_service() {
@@ -553,6 +662,30 @@ fn setup_keyboard_handling(ui: &WindowData) {
                 return true;
             }
 
+            if is_dmenu()
+                && let Some(status) = get_dmenu_custom_bind(k, m)
+            {
+                let query = w.input.as_ref().map(Entry::text).unwrap_or_default();
+                let text = dmenu_result(
+                    get_selected_item().as_ref(),
+                    &query,
+                    &format!("custom:{status}"),
+                );
+
+                if !is_service() {
+                    println!("{text}");
+                    process::exit(status);
+                }
+
+                send_message_with_status(text, status);
+
+                if !is_dmenu_keep_open() || is_dmenu_exit_after() {
+                    quit(&app, false);
+                }
+
+                return true;
+            }
+
             let mut keybind_action: Option<Action> = None;
 
             let mut provider = if !get_provider().is_empty() {
@@ -616,6 +749,11 @@ fn setup_keyboard_handling(ui: &WindowData) {
                         ACTION_SELECT_PREVIOUS => select_previous(),
                         ACTION_TOGGLE_EXACT => toggle_exact(),
                         ACTION_RESUME_LAST_QUERY => resume_last_query(),
+                        ACTION_TOGGLE_MARK | ACTION_MARK_ALL if !is_dmenu_multi() => {
+                            return false;
+                        }
+                        ACTION_TOGGLE_MARK => toggle_mark(),
+                        ACTION_MARK_ALL => dmenu_list::mark_all(),
                         action if action.starts_with(ACTION_QUICK_ACTIVATE) => {
                             if let Some((_, after)) = action.split_once(":") {
                                 let i: u32 = after.parse().unwrap();
@@ -718,8 +856,125 @@ fn setup_list_behavior(ui: &WindowData) {
         });
     });
 
-    ui.list.set_model(Some(&ui.selection));
-    ui.list.set_factory(Some(&factory));
+    ui.list.set_model(&ui.selection);
+    ui.list.set_factory(&factory);
+
+    // dmenu only puts the first matches into the list
+    ui.scroll.connect_edge_reached(|_, pos| {
+        if is_dmenu() && pos == PositionType::Bottom {
+            glib::idle_add_local_once(dmenu_list::show_more);
+        }
+    });
+
+    if let ResultList::List(list) = &ui.list
+        && ui.grouped.is_some()
+    {
+        list.set_header_factory(Some(&section_header_factory()));
+    }
+}
+
+// windows outlive a dmenu call, its lines are never grouped
+pub fn update_sections(ui: &WindowData) {
+    let (Some(grouped), ResultList::List(list)) = (&ui.grouped, &ui.list) else {
+        return;
+    };
+
+    let model: ListModel = if is_dmenu() {
+        ui.items.clone().upcast()
+    } else {
+        grouped.clone().upcast()
+    };
+
+    if ui.selection.model().as_ref() == Some(&model) {
+        return;
+    }
+
+    ui.selection.set_model(Some(&model));
+
+    if is_dmenu() {
+        list.set_header_factory(None::<&SignalListItemFactory>);
+    } else {
+        list.set_header_factory(Some(&section_header_factory()));
+    }
+}
+
+// sorts results into one section per provider. sections are ordered by their
+// best result, within a section the score order of `items` is kept.
+fn group_by_provider(items: &ListStore) -> SortListModel {
+    let ranks: Rc<RefCell<HashMap<String, u32>>> = Rc::default();
+
+    let provider = |obj: &Object| {
+        obj.downcast_ref::<QueryResponseObject>()
+            .and_then(|o| o.response().item.as_ref().map(|i| i.provider.clone()))
+            .unwrap_or_default()
+    };
+
+    let sorter = CustomSorter::new({
+        let ranks = ranks.clone();
+        move |a, b| {
+            let ranks = ranks.borrow();
+            let rank = |o: &Object| ranks.get(&provider(o)).copied().unwrap_or(u32::MAX);
+            rank(a).cmp(&rank(b)).into()
+        }
+    });
+
+    // connected before the sort model exists, so ranks are current when it resorts
+    items.connect_items_changed({
+        let sorter = sorter.clone();
+        move |items, _, _, _| {
+            let mut new: HashMap<String, u32> = HashMap::new();
+
+            for i in 0..items.n_items() {
+                if let Some(obj) = items.item(i) {
+                    let next = new.len() as u32;
+                    new.entry(provider(&obj)).or_insert(next);
+                }
+            }
+
+            if *ranks.borrow() != new {
+                *ranks.borrow_mut() = new;
+                sorter.changed(SorterChange::Different);
+            }
+        }
+    });
+
+    let model = SortListModel::new(Some(items.clone()), None::<CustomSorter>);
+    model.set_section_sorter(Some(&sorter));
+    model
+}
+
+fn section_header_factory() -> SignalListItemFactory {
+    let factory = SignalListItemFactory::new();
+
+    factory.connect_unbind(|_, header| {
+        let header = header
+            .downcast_ref::<ListHeader>()
+            .expect("failed casting to ListHeader");
+
+        header.set_child(None::<&gtk4::Widget>);
+    });
+
+    factory.connect_bind(|_, header| {
+        let header = header
+            .downcast_ref::<ListHeader>()
+            .expect("failed casting to ListHeader");
+
+        let Some(provider) = header
+            .item()
+            .and_downcast_ref::<QueryResponseObject>()
+            .and_then(|o| o.response().item.as_ref().map(|i| i.provider.clone()))
+        else {
+            return;
+        };
+
+        with_themes(|t| {
+            if let Some(theme) = t.get(&get_theme()) {
+                create_section_header(header, &provider, theme);
+            }
+        });
+    });
+
+    factory
 }
 
 fn setup_mouse_handling(ui: &WindowData) {
@@ -761,6 +1016,9 @@ pub fn quit(app: &Application, cancelled: bool) {
         send_message("CNCLD".to_string());
     }
 
+    stop_dmenu_input();
+    dmenu_list::clear();
+
     if !app
         .flags()
         .contains(gtk4::gio::ApplicationFlags::IS_SERVICE)
@@ -801,6 +1059,9 @@ pub fn quit(app: &Application, cancelled: bool) {
     set_placeholder(String::new());
     set_is_visible(false);
     set_dmenu_current(0);
+    set_dmenu_multi(false);
+    set_dmenu_with_nth(None);
+    set_dmenu_accept_nth(None);
     set_is_dmenu(false);
     set_input_only(false);
     set_param_close(false);
@@ -813,10 +1074,16 @@ pub fn quit(app: &Application, cancelled: bool) {
         set_dmenu_keep_open(false);
     }
 
-    gtk4::glib::idle_add_once(|| {
+    // a password is never remembered for resume_last_query
+    let password = is_password();
+    set_password(false);
+
+    gtk4::glib::idle_add_once(move || {
         with_window(|w| {
             if let Some(input) = &w.input {
-                set_last_query(input.text().to_string());
+                if !password {
+                    set_last_query(input.text().to_string());
+                }
 
                 if !get_initial_placeholder().is_empty() {
                     input.set_placeholder_text(Some(&get_initial_placeholder()));
@@ -932,6 +1199,31 @@ pub fn select_previous() {
     });
 }
 
+fn toggle_mark() {
+    with_window(|w| {
+        let Some(obj) = w
+            .selection
+            .selected_item()
+            .and_downcast::<QueryResponseObject>()
+        else {
+            return;
+        };
+
+        obj.set_marked(!obj.is_marked());
+
+        if let Some(item) = obj.response().item.as_ref() {
+            dmenu_list::set_marked(item, obj.is_marked());
+        }
+
+        // rebinds the row, so it picks up the class
+        if let Some(pos) = w.items.find(&obj) {
+            w.items.items_changed(pos, 1, 1);
+        }
+    });
+
+    select_next();
+}
+
 fn quick_activate(app: &Application, i: u32) {
     with_window(|w| {
         w.selection.set_selected(i);
@@ -941,7 +1233,7 @@ fn quick_activate(app: &Application, i: u32) {
 }
 
 fn resume_last_query() {
-    if !get_last_query().is_empty() {
+    if !get_last_query().is_empty() && !is_password() {
         set_input_text(&get_last_query());
     }
 }
@@ -1009,7 +1301,10 @@ pub fn handle_preview() {
             provider = "menus".to_string();
         }
 
-        if !crate::preview::has_previewer(&provider) {
+        // dmenu lines only have a preview with --preview or if the script gave them one
+        if !crate::preview::has_previewer(&provider)
+            || (provider == "dmenu" && item.preview.is_empty() && get_dmenu_preview().is_empty())
+        {
             preview.set_visible(false);
             return;
         }
@@ -1029,6 +1324,22 @@ pub fn handle_preview() {
     });
 }
 
+// while dmenu is still reading its input
+pub fn set_loading(loading: bool) {
+    with_window(|w| {
+        if loading {
+            w.window.add_css_class("loading");
+        } else {
+            w.window.remove_css_class("loading");
+        }
+
+        if let Some(spinner) = w.builder.object::<Spinner>("Loading") {
+            spinner.set_visible(loading);
+            spinner.set_spinning(loading);
+        }
+    });
+}
+
 pub fn set_keybind_hint() {
     with_window(|w| {
         let Some(k) = &w.keybinds else {